@@ -30,6 +30,7 @@ pub struct KubernetesCluster {
     pub vpc_uuid: String,
     pub ipv4: String,
     pub endpoint: String,
+    #[serde(default)]
     pub tags: Vec<String>,
     #[serde(rename = "node_pools")]
     pub node_pools: Vec<NodePool>,
@@ -48,6 +49,88 @@ pub struct KubernetesCluster {
     pub registry_enabled: bool,
 }
 
+/// summary of a Kubernetes cluster, enough to select clusters
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ClusterInfo {
+    pub id: String,
+    pub name: String,
+    pub region: String,
+    pub version: String,
+    pub tags: Vec<String>,
+    pub state: String,
+}
+
+impl From<KubernetesCluster> for ClusterInfo {
+    fn from(cluster: KubernetesCluster) -> Self {
+        ClusterInfo {
+            id: cluster.id,
+            name: cluster.name,
+            region: cluster.region,
+            version: cluster.version,
+            tags: cluster.tags,
+            state: cluster.status.state,
+        }
+    }
+}
+
+/// state of a Kubernetes cluster and of its node pools
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ClusterStatus {
+    /// ex: "running", "provisioning", "degraded", "error"
+    pub state: String,
+    pub message: Option<String>,
+    pub node_pools: Vec<NodePoolStatus>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct NodePoolStatus {
+    pub id: String,
+    pub name: String,
+    pub size: String,
+    /// number of nodes wanted in the pool
+    pub count: i64,
+    /// number of nodes of the pool up and running
+    pub running_nodes: usize,
+}
+
+impl ClusterStatus {
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+
+    /// the cluster won't become running on its own
+    pub fn is_failed(&self) -> bool {
+        ["error", "degraded", "invalid", "deleted"].contains(&self.state.as_str())
+    }
+}
+
+impl From<KubernetesCluster> for ClusterStatus {
+    fn from(cluster: KubernetesCluster) -> Self {
+        ClusterStatus {
+            state: cluster.status.state,
+            message: cluster.status.message,
+            node_pools: cluster.node_pools.iter().map(NodePoolStatus::from).collect(),
+        }
+    }
+}
+
+impl From<&NodePool> for NodePoolStatus {
+    fn from(pool: &NodePool) -> Self {
+        NodePoolStatus {
+            id: pool.id.clone(),
+            name: pool.name.clone(),
+            size: pool.size.clone(),
+            count: pool.count,
+            running_nodes: pool
+                .nodes
+                .iter()
+                .flatten()
+                .filter(|node| node.status.state == "running")
+                .count(),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NodePool {
@@ -100,6 +183,9 @@ pub struct MaintenancePolicy {
 #[serde(rename_all = "camelCase")]
 pub struct Status2 {
     pub state: String,
+    /// details about the state, ex: why the cluster is degraded
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
@@ -110,4 +196,27 @@ pub struct Meta {
 
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Links {}
+pub struct Links {
+    #[serde(default)]
+    pub pages: Option<Pages>,
+}
+
+/// links to the other pages of a list, absent on the last page
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pages {
+    pub first: Option<String>,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    pub last: Option<String>,
+}
+
+impl Clusters {
+    /// url of the next page, `None` on the last one
+    pub fn next_page(&self) -> Option<&str> {
+        self.links
+            .as_ref()
+            .and_then(|links| links.pages.as_ref())
+            .and_then(|pages| pages.next.as_deref())
+    }
+}
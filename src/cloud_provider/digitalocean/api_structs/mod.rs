@@ -1 +1,2 @@
 pub mod clusters;
+pub mod regions;
@@ -0,0 +1,20 @@
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Regions {
+    pub regions: Vec<RegionInfo>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct RegionInfo {
+    pub slug: String,
+    pub name: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    pub available: bool,
+}
+
+impl RegionInfo {
+    /// true if the region is available and supports the feature (ex: "kubernetes", "storage")
+    pub fn supports(&self, feature: &str) -> bool {
+        self.available && self.features.iter().any(|x| x == feature)
+    }
+}
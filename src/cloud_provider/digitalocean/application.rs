@@ -2,7 +2,6 @@ use serde::{Deserialize, Serialize};
 use tera::Context as TeraContext;
 
 use crate::build_platform::Image;
-use crate::cloud_provider::digitalocean::common::get_uuid_of_cluster;
 use crate::cloud_provider::digitalocean::{common, DO};
 use crate::cloud_provider::environment::Environment;
 use crate::cloud_provider::kubernetes::Kubernetes;
@@ -149,7 +148,7 @@ impl Create for Application {
         let workspace_dir = self.workspace_directory();
 
         // retrieve the cluster uuid, useful to link DO registry to k8s cluster
-        let cluster_uuid_res = get_uuid_of_cluster(digitalocean.token.as_str(), kubernetes.name());
+        let cluster_uuid_res = digitalocean.api_client().get_uuid_of_cluster(kubernetes.name());
         match cluster_uuid_res {
             // ensure DO registry is linked to k8s cluster
             Ok(uuid) => match subscribe_kube_cluster_to_container_registry(
@@ -183,7 +182,7 @@ impl Create for Application {
             digitalocean.region.as_str(),
             digitalocean.spaces_secret_key.as_str(),
             digitalocean.spaces_access_id.as_str(),
-
+            false,
         );
         match kubeconfig_path {
             Ok(path) => {
@@ -0,0 +1,1316 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cloud_provider::digitalocean::api_structs::clusters::{
+    Cluster, ClusterInfo, ClusterStatus, Clusters, KubernetesCluster,
+};
+use crate::cloud_provider::digitalocean::api_structs::regions::{RegionInfo, Regions};
+use crate::constants::USER_AGENT;
+use crate::container_registry::docr::get_header_with_bearer;
+use crate::error::{SimpleError, SimpleErrorKind};
+use crate::metrics::{
+    metrics, DIGITALOCEAN_API_CALL_COUNT, DIGITALOCEAN_API_CALL_DURATION_SECONDS, RETRY_COUNT,
+};
+use crate::proxy::{configure_proxy, ProxyConfig};
+use crate::string::redact_secrets;
+use crate::utilities::jittered_duration;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_NONE_MATCH, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use retry::delay::{jitter, Fibonacci, Fixed};
+use retry::OperationResult;
+use serde::de::DeserializeOwned;
+
+pub const DO_API_URL: &str = "https://api.digitalocean.com/v2";
+/// a warning is logged when the remaining requests of the rate limit window drop below this value
+const RATE_LIMIT_WARNING_THRESHOLD: u32 = 100;
+/// number of kubeconfigs downloaded at the same time by `fetch_all_kubeconfigs`
+const KUBECONFIG_FETCH_PARALLELISM: usize = 4;
+/// first delay between two lookups of `resolve_cluster_id_eventually`, the next ones follow a Fibonacci sequence
+const CLUSTER_ID_RESOLUTION_FIRST_DELAY_MILLIS: u64 = 1000;
+const NOT_FOUND_RETRY_JITTER: f64 = 0.2;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// maximum number of items per page of the DigitalOcean API lists (it defaults to 20)
+const DO_API_MAX_PER_PAGE: u32 = 200;
+/// guard against a `next` link looping on the same pages
+const MAX_LIST_PAGES: usize = 1000;
+/// attempts of an API call answered with a 429 or a 5xx, the first one included
+const API_MAX_ATTEMPTS: usize = 5;
+/// first delay between two attempts of an API call without `Retry-After`, the next ones follow a Fibonacci sequence
+const API_RETRY_FIRST_DELAY_MILLIS: u64 = 500;
+const API_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+pub type ClusterId = String;
+
+/// clients returned by `DigitalOceanClient::shared`, by token
+static SHARED_CLIENTS: Mutex<Option<HashMap<String, Arc<DigitalOceanClient>>>> = Mutex::new(None);
+
+/// DigitalOcean API rate limit, as returned by the last API response headers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitStatus {
+    /// `RateLimit-Limit`: number of requests allowed per window
+    pub limit: u32,
+    /// `RateLimit-Remaining`: number of requests left in the current window
+    pub remaining: u32,
+    /// `RateLimit-Reset`: unix timestamp (in seconds) when the window resets
+    pub reset: u64,
+}
+
+impl RateLimitStatus {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse::<u64>().ok())
+        };
+
+        Some(RateLimitStatus {
+            limit: header_value("RateLimit-Limit")? as u32,
+            remaining: header_value("RateLimit-Remaining")? as u32,
+            reset: header_value("RateLimit-Reset")?,
+        })
+    }
+
+    /// time left before the rate limit window resets
+    fn time_until_reset(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Duration::from_secs(self.reset)
+            .checked_sub(now)
+            .unwrap_or_default()
+    }
+}
+
+/// last responses of the cached GET calls with their `ETag`, the oldest one is evicted when full
+struct ResponseCache {
+    capacity: usize,
+    entries: VecDeque<(String, String, Arc<dyn Any + Send + Sync>)>,
+}
+
+impl ResponseCache {
+    fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// `ETag` and deserialized value of the last response of the path
+    fn get(&self, path: &str) -> Option<(String, Arc<dyn Any + Send + Sync>)> {
+        self.entries
+            .iter()
+            .find(|(entry_path, _, _)| entry_path == path)
+            .map(|(_, etag, value)| (etag.clone(), value.clone()))
+    }
+
+    fn insert(&mut self, path: &str, etag: String, value: Arc<dyn Any + Send + Sync>) {
+        self.entries.retain(|(entry_path, _, _)| entry_path != path);
+        if self.capacity == 0 {
+            return;
+        }
+
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((path.to_string(), etag, value));
+    }
+}
+
+/// TLS library used by the DigitalOcean client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsBackend {
+    /// the platform TLS library (OpenSSL on Linux), the default
+    NativeTls,
+    /// rustls, for environments requiring a reproducible TLS stack (requires the `rustls` feature)
+    #[cfg(feature = "rustls")]
+    Rustls,
+}
+
+pub struct DigitalOceanClientBuilder {
+    token: String,
+    tls_backend: TlsBackend,
+    http2_prior_knowledge: bool,
+    response_cache_capacity: Option<usize>,
+    user_agent_token: Option<String>,
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    root_certificates: Vec<Vec<u8>>,
+    per_page: u32,
+    proxy: ProxyConfig,
+}
+
+impl DigitalOceanClientBuilder {
+    pub fn tls_backend(mut self, tls_backend: TlsBackend) -> Self {
+        self.tls_backend = tls_backend;
+        self
+    }
+
+    /// talk HTTP/2 directly instead of HTTP/1.1. Without it, HTTP/2 is only used
+    /// if it is negotiated during the TLS handshake (ALPN, supported by rustls).
+    pub fn http2_prior_knowledge(mut self, http2_prior_knowledge: bool) -> Self {
+        self.http2_prior_knowledge = http2_prior_knowledge;
+        self
+    }
+
+    /// keep the last `capacity` responses of the slowly changing resources (ex: the region list)
+    /// and send their `ETag` in `If-None-Match`, so a `304 Not Modified` response returns the cached value
+    /// instead of downloading and parsing it again. Disabled by default.
+    pub fn response_cache(mut self, capacity: usize) -> Self {
+        self.response_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// append a token to the `qovery-engine/<version>` User-Agent (ex: "my-tool/1.2")
+    pub fn user_agent_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.user_agent_token = Some(token.into());
+        self
+    }
+
+    /// maximum time to establish the connection (TCP and TLS handshakes), 10s by default.
+    /// It is part of the `timeout`, so an unreachable host fails fast even if `timeout` is long.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// maximum time of a whole request, from connecting to reading the end of the response body,
+    /// 30s by default. `None` disables it (ex: for large downloads), `connect_timeout` still applies.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// trust this PEM encoded CA certificate in addition to the system roots
+    /// (ex: the CA of a TLS-intercepting proxy). Can be called several times.
+    pub fn add_root_certificate_pem(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    /// same as `add_root_certificate_pem`, with a PEM file
+    pub fn add_root_certificate_file(self, path: &Path) -> Result<Self, SimpleError> {
+        let pem = fs::read(path)?;
+        Ok(self.add_root_certificate_pem(pem.as_slice()))
+    }
+
+    /// number of items per page of the lists (clusters, regions...), from 1 to 200 (the default).
+    /// Bigger pages mean fewer requests.
+    pub fn per_page(mut self, per_page: u32) -> Self {
+        self.per_page = per_page.clamp(1, DO_API_MAX_PER_PAGE);
+        self
+    }
+
+    /// outbound proxies, read from `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` by default
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn build(self) -> Result<DigitalOceanClient, SimpleError> {
+        let user_agent = match self.user_agent_token.as_ref() {
+            Some(token) => format!("{} {}", USER_AGENT, token),
+            None => USER_AGENT.to_string(),
+        };
+        let builder = reqwest::blocking::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout);
+        let mut builder = configure_proxy(builder, &self.proxy);
+
+        builder = match self.tls_backend {
+            TlsBackend::NativeTls => builder.use_native_tls(),
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls => builder.use_rustls_tls(),
+        };
+
+        for pem in &self.root_certificates {
+            match reqwest::Certificate::from_pem(pem.as_slice()) {
+                Ok(certificate) => builder = builder.add_root_certificate(certificate),
+                Err(err) => {
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(format!("invalid PEM CA certificate: {}", err)),
+                    ))
+                }
+            }
+        }
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        let http_client = match builder.build() {
+            Ok(http_client) => http_client,
+            Err(err) => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "unable to create the Digital Ocean HTTP client: {}",
+                        err
+                    )),
+                ))
+            }
+        };
+
+        Ok(DigitalOceanClient {
+            token: self.token,
+            api_url: DO_API_URL.to_string(),
+            http_client,
+            connect_timeout: self.connect_timeout,
+            timeout: self.timeout,
+            rate_limit_status: Arc::new(Mutex::new(None)),
+            response_cache: self
+                .response_cache_capacity
+                .map(|capacity| Arc::new(Mutex::new(ResponseCache::new(capacity)))),
+            per_page: self.per_page,
+        })
+    }
+}
+
+/// DigitalOcean API client.
+///
+/// It holds a single `reqwest` client, and therefore a single pool of keep-alive connections,
+/// reused by every call made through it. It is cheap to clone (clones share the same connection pool)
+/// and it is `Send + Sync`, so create it once and pass it around instead of creating a new one per call.
+/// Clones also share the last known rate limit status and the response cache.
+#[derive(Clone)]
+pub struct DigitalOceanClient {
+    token: String,
+    api_url: String,
+    http_client: reqwest::blocking::Client,
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    rate_limit_status: Arc<Mutex<Option<RateLimitStatus>>>,
+    response_cache: Option<Arc<Mutex<ResponseCache>>>,
+    per_page: u32,
+}
+
+impl DigitalOceanClient {
+    /// client with the defaults, same as `DigitalOceanClient::builder(token).build()`.
+    /// It fails if the HTTP client cannot be created (ex: the TLS backend cannot be initialized).
+    pub fn new(token: &str) -> Result<Self, SimpleError> {
+        DigitalOceanClient::builder(token).build()
+    }
+
+    /// client shared by every caller using the same token, created with the defaults on first use,
+    /// so the free functions of `common.rs` reuse the same connections (and rate limit status)
+    pub fn shared(token: &str) -> Result<Arc<DigitalOceanClient>, SimpleError> {
+        let mut clients = SHARED_CLIENTS.lock().unwrap();
+        let clients = clients.get_or_insert_with(HashMap::new);
+        if let Some(client) = clients.get(token) {
+            return Ok(client.clone());
+        }
+
+        let client = Arc::new(DigitalOceanClient::new(token)?);
+        clients.insert(token.to_string(), client.clone());
+        Ok(client)
+    }
+
+    /// configure the underlying HTTP client, `DigitalOceanClient::new` uses the defaults
+    pub fn builder(token: &str) -> DigitalOceanClientBuilder {
+        DigitalOceanClientBuilder {
+            token: token.to_string(),
+            tls_backend: TlsBackend::NativeTls,
+            http2_prior_knowledge: false,
+            response_cache_capacity: None,
+            user_agent_token: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            root_certificates: vec![],
+            per_page: DO_API_MAX_PER_PAGE,
+            proxy: ProxyConfig::from_env(),
+        }
+    }
+
+    /// rate limit status returned by the last API call, if any
+    pub fn rate_limit_status(&self) -> Option<RateLimitStatus> {
+        *self.rate_limit_status.lock().unwrap()
+    }
+
+    /// sleep until the rate limit window resets if there is no request left,
+    /// instead of sending a request that will be rejected with a 429
+    fn wait_for_rate_limit(&self) {
+        if let Some(status) = self.rate_limit_status() {
+            if status.remaining == 0 {
+                let wait = status.time_until_reset();
+                if wait > Duration::from_secs(0) {
+                    warn!(
+                        "Digital Ocean API rate limit reached, waiting {}s for it to reset",
+                        wait.as_secs()
+                    );
+                    thread::sleep(wait);
+                }
+            }
+        }
+    }
+
+    fn update_rate_limit_status(&self, headers: &HeaderMap) {
+        let status = match RateLimitStatus::from_headers(headers) {
+            Some(status) => status,
+            None => return,
+        };
+
+        if status.remaining < RATE_LIMIT_WARNING_THRESHOLD {
+            warn!(
+                "Digital Ocean API rate limit is almost reached: {}/{} requests remaining",
+                status.remaining, status.limit
+            );
+        }
+
+        *self.rate_limit_status.lock().unwrap() = Some(status);
+    }
+
+    fn get(&self, path: &str) -> Result<Response, SimpleError> {
+        self.get_with_headers(path, get_header_with_bearer(self.token.as_str()))
+    }
+
+    fn get_with_headers(&self, path: &str, headers: HeaderMap) -> Result<Response, SimpleError> {
+        self.request(Method::GET, path, headers)
+    }
+
+    fn delete(&self, path: &str) -> Result<Response, SimpleError> {
+        self.request(
+            Method::DELETE,
+            path,
+            get_header_with_bearer(self.token.as_str()),
+        )
+    }
+
+    /// send the request, retrying on 429 and 5xx responses up to `API_MAX_ATTEMPTS` times.
+    /// The last response is returned once the attempts are exhausted, other statuses are returned as is.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: HeaderMap,
+    ) -> Result<Response, SimpleError> {
+        let mut backoff = Fibonacci::from_millis(API_RETRY_FIRST_DELAY_MILLIS);
+        let mut attempt = 1;
+
+        loop {
+            let response = self.send(method.clone(), path, headers.clone())?;
+            if attempt >= API_MAX_ATTEMPTS {
+                return Ok(response);
+            }
+
+            let delay = match retry_delay(response.status(), response.headers(), &mut backoff) {
+                Some(delay) => delay,
+                None => return Ok(response),
+            };
+
+            warn!(
+                "Digital Ocean API answered {} on {} {}, retrying in {:?} (attempt {}/{})",
+                response.status().as_u16(),
+                method,
+                path,
+                delay,
+                attempt,
+                API_MAX_ATTEMPTS
+            );
+            metrics().increment(RETRY_COUNT, 1, &[("operation", "digitalocean_api")]);
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        path: &str,
+        headers: HeaderMap,
+    ) -> Result<Response, SimpleError> {
+        self.wait_for_rate_limit();
+
+        let started_at = Instant::now();
+        let response = self
+            .http_client
+            .request(method, format!("{}{}", self.api_url, path).as_str())
+            .headers(headers)
+            .send();
+
+        let metrics = metrics();
+        let status = match &response {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(_) => "error".to_string(),
+        };
+        metrics.increment(
+            DIGITALOCEAN_API_CALL_COUNT,
+            1,
+            &[("status", status.as_str())],
+        );
+        metrics.observe(
+            DIGITALOCEAN_API_CALL_DURATION_SECONDS,
+            started_at.elapsed().as_secs_f64(),
+            &[],
+        );
+
+        let response = response.map_err(|err| self.request_error(err))?;
+        self.update_rate_limit_status(response.headers());
+        Ok(response)
+    }
+
+    /// a timed out request fails with a `Timeout` error of the configured timeout
+    fn request_error(&self, err: reqwest::Error) -> SimpleError {
+        if !err.is_timeout() {
+            return SimpleError::from(err);
+        }
+
+        let timeout = self.timeout.unwrap_or(self.connect_timeout);
+        SimpleError::new(
+            SimpleErrorKind::Timeout(timeout),
+            Some(format!(
+                "the Digital Ocean API did not answer within {} seconds: {}",
+                timeout.as_secs(),
+                err
+            )),
+        )
+        .with_source(err)
+    }
+
+    /// path of the first page of a list
+    fn list_path(&self, path: &str) -> String {
+        format!("{}?per_page={}", path, self.per_page)
+    }
+
+    /// GET and deserialize `path`, going through the response cache if it is enabled.
+    /// `what` describes the resource in error messages (ex: "the region list").
+    fn get_json<T>(&self, path: &str, what: &str) -> Result<T, SimpleError>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let cached = self
+            .response_cache
+            .as_ref()
+            .and_then(|cache| cache.lock().unwrap().get(path));
+
+        let mut headers = get_header_with_bearer(self.token.as_str());
+        if let Some((etag, _)) = &cached {
+            if let Ok(etag) = HeaderValue::from_str(etag.as_str()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            }
+        }
+
+        let response = self.get_with_headers(path, headers)?;
+
+        match response.status() {
+            StatusCode::NOT_MODIFIED => {
+                if let Some(value) =
+                    cached.and_then(|(_, value)| value.downcast_ref::<T>().cloned())
+                {
+                    return Ok(value);
+                }
+            }
+            StatusCode::OK => {
+                let etag = response
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|x| x.to_str().ok())
+                    .map(|x| x.to_string());
+
+                return match serde_json::from_str::<T>(&response.text()?) {
+                    Ok(value) => {
+                        if let (Some(cache), Some(etag)) = (self.response_cache.as_ref(), etag) {
+                            cache
+                                .lock()
+                                .unwrap()
+                                .insert(path, etag, Arc::new(value.clone()));
+                        }
+                        Ok(value)
+                    }
+                    Err(e) => Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(format!(
+                            "While trying to deserialize json received from Digital Ocean API: {}",
+                            e
+                        )),
+                    )),
+                };
+            }
+            _ => {}
+        }
+
+        Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "Receive status code {} from Digital Ocean while retrieving {}",
+                response.status(),
+                what
+            )),
+        ))
+    }
+
+    pub fn get_uuid_of_cluster(&self, kube_name: &str) -> Result<String, SimpleError> {
+        let has_name = |cluster: &KubernetesCluster| cluster.name == kube_name;
+        let clusters = self.list_clusters_until(|page| find_cluster(page, has_name).is_some())?;
+        match find_cluster(&clusters, has_name) {
+            Some(cluster) => Ok(cluster.id.clone()),
+            None => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!(
+                    "Unable to retrieve cluster id from this name: {}",
+                    kube_name
+                )),
+            )),
+        }
+    }
+
+    pub fn get_cluster_name_for(&self, cluster_uuid: &str) -> Result<String, SimpleError> {
+        let has_id = |cluster: &KubernetesCluster| cluster.id == cluster_uuid;
+        let clusters = self.list_clusters_until(|page| find_cluster(page, has_id).is_some())?;
+        match find_cluster(&clusters, has_id) {
+            Some(cluster) => Ok(cluster.name.clone()),
+            None => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!(
+                    "Unable to retrieve cluster name from this id: {}",
+                    cluster_uuid
+                )),
+            )),
+        }
+    }
+
+    /// same as `get_uuid_of_cluster`, but retry while the cluster is not found, until `timeout` is elapsed:
+    /// a cluster just created may take a while to be listed by the API. Other errors are not retried.
+    pub fn resolve_cluster_id_eventually(
+        &self,
+        kube_name: &str,
+        timeout: Duration,
+    ) -> Result<String, SimpleError> {
+        retry_while_not_found(
+            timeout,
+            Fibonacci::from_millis(CLUSTER_ID_RESOLUTION_FIRST_DELAY_MILLIS),
+            || self.get_uuid_of_cluster(kube_name),
+        )
+    }
+
+    pub fn list_clusters(&self) -> Result<Vec<KubernetesCluster>, SimpleError> {
+        self.list_clusters_until(|_| false)
+    }
+
+    /// fetch the pages of the cluster list, following `links.pages.next`, until `found` is true for a page
+    /// or the pages are exhausted. Returns the clusters of the fetched pages.
+    fn list_clusters_until<F>(&self, mut found: F) -> Result<Vec<KubernetesCluster>, SimpleError>
+    where
+        F: FnMut(&[KubernetesCluster]) -> bool,
+    {
+        let mut clusters = vec![];
+        let mut path = Some(self.list_path("/kubernetes/clusters"));
+        let mut fetched_pages = 0;
+
+        while let Some(page_path) = path {
+            if fetched_pages >= MAX_LIST_PAGES {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "The cluster list from Digital Ocean has more than {} pages",
+                        MAX_LIST_PAGES
+                    )),
+                ));
+            }
+
+            let page = self.get_clusters_page(page_path.as_str())?;
+            fetched_pages += 1;
+            path = match page.next_page() {
+                Some(next) => Some(self.next_page_path(next)?),
+                None => None,
+            };
+
+            let stop = found(&page.kubernetes_clusters);
+            clusters.extend(page.kubernetes_clusters);
+            if stop {
+                break;
+            }
+        }
+
+        Ok(clusters)
+    }
+
+    fn get_clusters_page(&self, path: &str) -> Result<Clusters, SimpleError> {
+        let response = self.get(path)?;
+        match response.status() {
+            StatusCode::OK => {
+                let content = response.text()?;
+                let res_clusters = serde_json::from_str::<Clusters>(&content);
+                match res_clusters {
+                    Ok(clusters) => Ok(clusters),
+                    Err(e) => Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(format!(
+                            "While trying to deserialize json received from Digital Ocean API: {}",
+                            e
+                        )),
+                    )),
+                }
+            }
+            _ => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(
+                    "Receive weird status Code from Digital Ocean while retrieving the cluster list",
+                ),
+            )),
+        }
+    }
+
+    /// the API returns absolute urls in `links.pages`, requests are made with a path relative to the API url
+    fn next_page_path(&self, next: &str) -> Result<String, SimpleError> {
+        match next.strip_prefix(self.api_url.as_str()) {
+            Some(path) if !path.is_empty() => Ok(path.to_string()),
+            _ => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "Receive a next page link outside of the Digital Ocean API: {}",
+                    next
+                )),
+            )),
+        }
+    }
+
+    /// clusters having the tag, an empty list if none matches
+    pub fn find_clusters_by_tag(&self, tag: &str) -> Result<Vec<ClusterInfo>, SimpleError> {
+        Ok(self
+            .list_clusters()?
+            .into_iter()
+            .filter(|cluster| cluster.tags.iter().any(|x| x == tag))
+            .map(ClusterInfo::from)
+            .collect())
+    }
+
+    pub fn get_cluster(&self, cluster_uuid: &str) -> Result<KubernetesCluster, SimpleError> {
+        let response = self.get(format!("/kubernetes/clusters/{}", cluster_uuid).as_str())?;
+
+        match response.status() {
+            StatusCode::OK => match serde_json::from_str::<Cluster>(&response.text()?) {
+                Ok(cluster) => Ok(cluster.kubernetes_cluster),
+                Err(e) => Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "While trying to deserialize json received from Digital Ocean API: {}",
+                        e
+                    )),
+                )),
+            },
+            status => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "Receive status code {} from Digital Ocean while retrieving the cluster {}",
+                    status, cluster_uuid
+                )),
+            )),
+        }
+    }
+
+    /// state of the cluster and of its node pools
+    pub fn get_cluster_status(&self, cluster_uuid: &str) -> Result<ClusterStatus, SimpleError> {
+        Ok(ClusterStatus::from(self.get_cluster(cluster_uuid)?))
+    }
+
+    /// poll the cluster status until it is running. Fails with a `Timeout` error once `timeout` is elapsed,
+    /// and right away if the cluster is in a failed state (ex: "error", "degraded").
+    /// Each poll waits a random delay up to `poll_interval` (`retry::delay::jitter`).
+    pub fn wait_for_cluster_ready(
+        &self,
+        cluster_uuid: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<(), SimpleError> {
+        let delays = Fixed::from(poll_interval).map(jitter);
+        wait_until_running(cluster_uuid, timeout, delays, || {
+            self.get_cluster_status(cluster_uuid)
+        })
+    }
+
+    /// delete the cluster, it fails with `SimpleErrorKind::NotFound` if there is no cluster with this id
+    pub fn delete_cluster(&self, cluster_uuid: &str) -> Result<(), SimpleError> {
+        let response = self.delete(format!("/kubernetes/clusters/{}", cluster_uuid).as_str())?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::NOT_FOUND => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!("cluster not found: {}", cluster_uuid)),
+            )),
+            status => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "Receive status code {} from Digital Ocean while deleting the cluster {}",
+                    status, cluster_uuid
+                )),
+            )),
+        }
+    }
+
+    /// list every DigitalOcean region with the features it supports and whether it is available
+    pub fn list_regions(&self) -> Result<Vec<RegionInfo>, SimpleError> {
+        let regions =
+            self.get_json::<Regions>(self.list_path("/regions").as_str(), "the region list")?;
+        Ok(regions.regions)
+    }
+
+    /// fail with a clear message if the region does not exist, is not available
+    /// or does not support one of the features (ex: "kubernetes", "storage")
+    pub fn ensure_region_supports(
+        &self,
+        region: &str,
+        features: &[&str],
+    ) -> Result<(), SimpleError> {
+        let regions = self.list_regions()?;
+        let region_info = match regions.iter().find(|x| x.slug == region) {
+            Some(region_info) => region_info,
+            None => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "Digital Ocean region {} does not exist (available regions: {})",
+                        region,
+                        regions
+                            .iter()
+                            .filter(|x| x.available)
+                            .map(|x| x.slug.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )),
+                ))
+            }
+        };
+
+        let missing_features = features
+            .iter()
+            .filter(|x| !region_info.supports(x))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if missing_features.is_empty() {
+            return Ok(());
+        }
+
+        Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "Digital Ocean region {} does not support {}",
+                region,
+                missing_features.join(", ")
+            )),
+        ))
+    }
+
+    /// kubeconfig of the cluster, as returned by the DigitalOcean API
+    pub fn get_kubeconfig(&self, cluster_uuid: &str) -> Result<String, SimpleError> {
+        let path = format!("/kubernetes/clusters/{}/kubeconfig", cluster_uuid);
+        let response = self.get(path.as_str())?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.text()?),
+            StatusCode::NOT_FOUND => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!("cluster {} not found", cluster_uuid)),
+            )),
+            status => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "Receive status code {} from Digital Ocean while retrieving the kubeconfig of the cluster {}",
+                    status, cluster_uuid
+                )),
+            )),
+        }
+    }
+
+    /// write the kubeconfig of the cluster into `dest_dir` and return its path
+    pub fn download_kubeconfig(
+        &self,
+        cluster_uuid: &str,
+        dest_dir: &Path,
+    ) -> Result<PathBuf, SimpleError> {
+        let kubeconfig = self.get_kubeconfig(cluster_uuid)?;
+        let kubeconfig_path = dest_dir.join(format!("kubernetes_config_{}", cluster_uuid));
+        fs::write(&kubeconfig_path, kubeconfig)?;
+        Ok(kubeconfig_path)
+    }
+
+    /// download the kubeconfig of every cluster of the account into `dest_dir`,
+    /// `KUBECONFIG_FETCH_PARALLELISM` at a time.
+    ///
+    /// A failing (or panicking) download does not abort the others: failures are logged and
+    /// the kubeconfigs successfully downloaded are returned. It only fails if the clusters can't be
+    /// listed or if every download failed, with the error of each cluster.
+    pub fn fetch_all_kubeconfigs(
+        &self,
+        dest_dir: &Path,
+    ) -> Result<Vec<(ClusterId, PathBuf)>, SimpleError> {
+        let cluster_ids = self
+            .list_clusters()?
+            .into_iter()
+            .map(|cluster| cluster.id)
+            .collect::<Vec<_>>();
+
+        if cluster_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let pending = Arc::new(Mutex::new(cluster_ids.into_iter()));
+        let workers = (0..KUBECONFIG_FETCH_PARALLELISM)
+            .map(|_| {
+                let client = self.clone();
+                let pending = pending.clone();
+                let dest_dir = dest_dir.to_path_buf();
+
+                thread::spawn(move || {
+                    let mut results = vec![];
+                    loop {
+                        let cluster_id = match pending.lock().unwrap().next() {
+                            Some(cluster_id) => cluster_id,
+                            None => return results,
+                        };
+
+                        // a panicking download is a failure of its cluster, the others go on
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            client.download_kubeconfig(cluster_id.as_str(), &dest_dir)
+                        }))
+                        .unwrap_or_else(|_| {
+                            Err(SimpleError::new(
+                                SimpleErrorKind::Other,
+                                Some("the download panicked"),
+                            ))
+                        });
+                        results.push((cluster_id, result));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut kubeconfigs = vec![];
+        let mut failures = vec![];
+        for worker in workers {
+            let results = match worker.join() {
+                Ok(results) => results,
+                Err(_) => {
+                    error!("a kubeconfig download worker panicked, its clusters are missing");
+                    failures.push("a download worker panicked".to_string());
+                    continue;
+                }
+            };
+
+            for (cluster_id, result) in results {
+                match result {
+                    Ok(kubeconfig_path) => kubeconfigs.push((cluster_id, kubeconfig_path)),
+                    Err(err) => {
+                        let message = redact_secrets(
+                            err.message.unwrap_or_default().as_str(),
+                            &[self.token.as_str()],
+                        );
+                        error!(
+                            "unable to download the kubeconfig of the cluster {}: {}",
+                            cluster_id, message
+                        );
+                        failures.push(format!("{}: {}", cluster_id, message));
+                    }
+                }
+            }
+        }
+
+        if kubeconfigs.is_empty() && !failures.is_empty() {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "unable to download any kubeconfig: {}",
+                    failures.join(", ")
+                )),
+            ));
+        }
+
+        Ok(kubeconfigs)
+    }
+
+    /// poll the cluster until its state is `running`, or fail once `timeout` is elapsed,
+    /// and right away if the cluster is in a failed state (ex: "error", "degraded").
+    /// Each poll waits `poll_interval` randomized by +/- `jitter` (fraction of the interval, ex: 0.2)
+    /// to avoid many engines polling the DigitalOcean API at the same time.
+    pub fn wait_for_cluster_running(
+        &self,
+        cluster_uuid: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+        jitter: f64,
+    ) -> Result<(), SimpleError> {
+        let delays = Fixed::from(poll_interval).map(|x| jittered_duration(x, jitter));
+        wait_until_running(cluster_uuid, timeout, delays, || {
+            self.get_cluster_status(cluster_uuid)
+        })
+    }
+}
+
+/// call `status` with `retry::retry`, waiting each delay of `delays`, until the cluster is running.
+/// Fails with a `Timeout` error once `timeout` is elapsed or the delays are exhausted,
+/// and right away if the cluster is in a failed state.
+fn wait_until_running<D, F>(
+    cluster_uuid: &str,
+    timeout: Duration,
+    delays: D,
+    mut status: F,
+) -> Result<(), SimpleError>
+where
+    D: Iterator<Item = Duration>,
+    F: FnMut() -> Result<ClusterStatus, SimpleError>,
+{
+    let started_at = Instant::now();
+    let remaining = move || {
+        timeout
+            .checked_sub(started_at.elapsed())
+            .unwrap_or_default()
+    };
+    let delays = delays
+        .take_while(move |_| remaining() > Duration::from_secs(0))
+        .map(move |x| x.min(remaining()));
+
+    let result = retry::retry(delays, || match status() {
+        Ok(status) if status.is_running() => OperationResult::Ok(()),
+        Ok(status) if status.is_failed() => OperationResult::Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "cluster {} is {}: {}",
+                cluster_uuid,
+                status.state,
+                status.message.unwrap_or_default()
+            )),
+        )),
+        Ok(status) => {
+            info!(
+                "cluster {} is {}, waiting for it to be running",
+                cluster_uuid, status.state
+            );
+            OperationResult::Retry(SimpleError::new(
+                SimpleErrorKind::Timeout(timeout),
+                Some(format!(
+                    "cluster {} is still {} and not running",
+                    cluster_uuid, status.state
+                )),
+            ))
+        }
+        Err(err) => OperationResult::Err(err),
+    });
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(retry::Error::Operation { error, .. }) => Err(error),
+        Err(retry::Error::Internal(err)) => {
+            Err(SimpleError::new(SimpleErrorKind::Other, Some(err)))
+        }
+    }
+}
+
+/// delay before retrying a response with this status, `None` if it must not be retried.
+/// Only 429 and 5xx are retried, waiting `Retry-After` seconds if the header is set or the next backoff delay otherwise.
+fn retry_delay<D>(status: StatusCode, headers: &HeaderMap, backoff: &mut D) -> Option<Duration>
+where
+    D: Iterator<Item = Duration>,
+{
+    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+        return None;
+    }
+
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+
+    let delay = match retry_after {
+        Some(delay) => delay,
+        None => backoff.next()?,
+    };
+    Some(delay.min(API_MAX_RETRY_DELAY))
+}
+
+/// call `f` until it returns something else than a `NotFound` error, waiting the next of `delays`
+/// (randomized) between two calls. Fails with a `Timeout` error once `timeout` is elapsed.
+fn retry_while_not_found<T, D, F>(
+    timeout: Duration,
+    mut delays: D,
+    mut f: F,
+) -> Result<T, SimpleError>
+where
+    D: Iterator<Item = Duration>,
+    F: FnMut() -> Result<T, SimpleError>,
+{
+    let started_at = Instant::now();
+
+    loop {
+        let err = match f() {
+            Err(err) if matches!(err.kind, SimpleErrorKind::NotFound) => err,
+            result => return result,
+        };
+
+        let elapsed = started_at.elapsed();
+        if elapsed >= timeout {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Timeout(timeout),
+                Some(format!(
+                    "still not found after {} seconds: {}",
+                    timeout.as_secs(),
+                    err.message.unwrap_or_default()
+                )),
+            ));
+        }
+
+        let delay = delays.next().unwrap_or(timeout - elapsed);
+        debug!(
+            "{}, retrying in {:?}",
+            err.message.unwrap_or_default(),
+            delay
+        );
+        thread::sleep(jittered_duration(delay, NOT_FOUND_RETRY_JITTER).min(timeout - elapsed));
+    }
+}
+
+/// first cluster matching `predicate`
+fn find_cluster<P>(clusters: &[KubernetesCluster], predicate: P) -> Option<&KubernetesCluster>
+where
+    P: Fn(&KubernetesCluster) -> bool,
+{
+    clusters.iter().find(|cluster| predicate(cluster))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud_provider::digitalocean::api_structs::clusters::{
+        ClusterStatus, Clusters, KubernetesCluster, Node, NodePool, Status, Status2,
+    };
+    use crate::cloud_provider::digitalocean::client::{
+        find_cluster, retry_delay, retry_while_not_found, wait_until_running, DigitalOceanClient,
+        ResponseCache,
+    };
+    use crate::error::{SimpleError, SimpleErrorKind};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::StatusCode;
+    use std::iter;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_shared_client() {
+        let client = DigitalOceanClient::shared("token-1").unwrap();
+        assert!(Arc::ptr_eq(
+            &client,
+            &DigitalOceanClient::shared("token-1").unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &client,
+            &DigitalOceanClient::shared("token-2").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_invalid_root_certificate() {
+        assert!(DigitalOceanClient::builder("token")
+            .add_root_certificate_pem(b"not a certificate")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let mut cache = ResponseCache::new(2);
+        cache.insert("/regions", "\"a\"".to_string(), Arc::new(1));
+        cache.insert("/sizes", "\"b\"".to_string(), Arc::new(2));
+        cache.insert("/regions", "\"c\"".to_string(), Arc::new(3));
+
+        let (etag, value) = cache.get("/regions").unwrap();
+        assert_eq!(etag, "\"c\"");
+        assert_eq!(value.downcast_ref::<i32>(), Some(&3));
+
+        // the oldest entry is evicted once the cache is full
+        cache.insert("/options", "\"d\"".to_string(), Arc::new(4));
+        assert!(cache.get("/sizes").is_none());
+        assert!(cache.get("/regions").is_some());
+        assert!(cache.get("/options").is_some());
+    }
+
+    #[test]
+    fn test_retry_while_not_found() {
+        let delays = || iter::repeat(Duration::from_millis(1));
+        let not_found = || SimpleError::new(SimpleErrorKind::NotFound, Some("not listed yet"));
+
+        let mut calls = 0;
+        let result = retry_while_not_found(Duration::from_secs(10), delays(), || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(not_found()),
+                _ => Ok("cluster-uuid"),
+            }
+        });
+        assert_eq!(result.unwrap(), "cluster-uuid");
+        assert_eq!(calls, 3);
+
+        // only NotFound is retried
+        let mut calls = 0;
+        let err = retry_while_not_found::<(), _, _>(Duration::from_secs(10), delays(), || {
+            calls += 1;
+            Err(SimpleError::new(
+                SimpleErrorKind::Unauthorized,
+                Some("bad token"),
+            ))
+        })
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Unauthorized));
+        assert_eq!(calls, 1);
+
+        let err = retry_while_not_found::<(), _, _>(Duration::from_millis(20), delays(), || {
+            Err(not_found())
+        })
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Timeout(_)));
+    }
+
+    #[test]
+    fn test_next_page_path() {
+        let page = serde_json::from_str::<Clusters>(
+            r#"{
+                "kubernetes_clusters": [],
+                "meta": {"total": 250},
+                "links": {"pages": {
+                    "next": "https://api.digitalocean.com/v2/kubernetes/clusters?page=2&per_page=200",
+                    "last": "https://api.digitalocean.com/v2/kubernetes/clusters?page=2&per_page=200"
+                }}
+            }"#,
+        )
+        .unwrap();
+
+        let client = DigitalOceanClient::new("token").unwrap();
+        assert_eq!(
+            client.next_page_path(page.next_page().unwrap()).unwrap(),
+            "/kubernetes/clusters?page=2&per_page=200"
+        );
+        assert!(client
+            .next_page_path("https://example.com/v2/kubernetes/clusters?page=2")
+            .is_err());
+
+        let last_page =
+            serde_json::from_str::<Clusters>(r#"{"kubernetes_clusters": [], "links": {}}"#)
+                .unwrap();
+        assert_eq!(last_page.next_page(), None);
+    }
+
+    #[test]
+    fn test_find_cluster() {
+        let cluster = |id: &str, name: &str| KubernetesCluster {
+            id: id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let clusters = vec![cluster("uuid-1", "prod"), cluster("uuid-2", "staging")];
+
+        assert_eq!(
+            find_cluster(&clusters, |x| x.name == "staging").map(|x| x.id.as_str()),
+            Some("uuid-2")
+        );
+        assert_eq!(
+            find_cluster(&clusters, |x| x.id == "uuid-1").map(|x| x.name.as_str()),
+            Some("prod")
+        );
+        assert!(find_cluster(&clusters, |x| x.name == "dev").is_none());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let mut backoff = iter::repeat(Duration::from_millis(500));
+        let no_headers = HeaderMap::new();
+        let mut retry_after = HeaderMap::new();
+        retry_after.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+
+        assert_eq!(
+            retry_delay(StatusCode::TOO_MANY_REQUESTS, &retry_after, &mut backoff),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            retry_delay(StatusCode::BAD_GATEWAY, &no_headers, &mut backoff),
+            Some(Duration::from_millis(500))
+        );
+
+        let mut too_long = HeaderMap::new();
+        too_long.insert(RETRY_AFTER, HeaderValue::from_static("3600"));
+        assert_eq!(
+            retry_delay(StatusCode::SERVICE_UNAVAILABLE, &too_long, &mut backoff),
+            Some(Duration::from_secs(60))
+        );
+
+        // other statuses fail fast
+        assert_eq!(
+            retry_delay(StatusCode::NOT_FOUND, &retry_after, &mut backoff),
+            None
+        );
+        assert_eq!(
+            retry_delay(StatusCode::UNAUTHORIZED, &no_headers, &mut backoff),
+            None
+        );
+        assert_eq!(retry_delay(StatusCode::OK, &no_headers, &mut backoff), None);
+    }
+
+    #[test]
+    fn test_cluster_status() {
+        let node = |state: &str| Node {
+            status: Status {
+                state: state.to_string(),
+            },
+            ..Default::default()
+        };
+        let cluster = KubernetesCluster {
+            status: Status2 {
+                state: "provisioning".to_string(),
+                message: Some("provisioning the nodes".to_string()),
+            },
+            node_pools: vec![NodePool {
+                id: "pool-1".to_string(),
+                name: "default".to_string(),
+                size: "s-2vcpu-4gb".to_string(),
+                count: 3,
+                nodes: Some(vec![node("running"), node("provisioning"), node("running")]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let status = ClusterStatus::from(cluster);
+        assert!(!status.is_running());
+        assert_eq!(status.message.as_deref(), Some("provisioning the nodes"));
+        assert_eq!(status.node_pools.len(), 1);
+        assert_eq!(status.node_pools[0].count, 3);
+        assert_eq!(status.node_pools[0].running_nodes, 2);
+    }
+
+    #[test]
+    fn test_wait_until_running() {
+        let delays = || iter::repeat(Duration::from_millis(1));
+        let status = |state: &str| ClusterStatus {
+            state: state.to_string(),
+            ..Default::default()
+        };
+
+        let mut states = vec!["running", "provisioning", "provisioning"];
+        let result = wait_until_running("uuid", Duration::from_secs(10), delays(), || {
+            Ok(status(states.pop().unwrap()))
+        });
+        assert!(result.is_ok());
+        assert!(states.is_empty());
+
+        let err = wait_until_running("uuid", Duration::from_secs(10), delays(), || {
+            Ok(status("degraded"))
+        })
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Other));
+
+        let err = wait_until_running("uuid", Duration::from_millis(20), delays(), || {
+            Ok(status("provisioning"))
+        })
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Timeout(_)));
+    }
+}
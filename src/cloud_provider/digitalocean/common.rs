@@ -1,19 +1,98 @@
-use crate::cloud_provider::digitalocean::api_structs::clusters::Clusters;
-use crate::container_registry::docr::get_header_with_bearer;
+use crate::cloud_provider::digitalocean::api_structs::clusters::{ClusterInfo, ClusterStatus};
+use crate::cloud_provider::digitalocean::api_structs::regions::RegionInfo;
+use crate::cloud_provider::digitalocean::client::{ClusterId, DigitalOceanClient};
+use crate::cloud_provider::digitalocean::region::DoRegion;
 use crate::error::{SimpleError, SimpleErrorKind};
-use crate::object_storage::do_space::download_space_object;
-use reqwest::StatusCode;
+use crate::kubeconfig::{validate_kubeconfig, KubeconfigGuard};
+use crate::object_storage::do_space::DoSpace;
+use crate::object_storage::ObjectStorage;
+use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 extern crate serde_json;
 
+/// how long a kubeconfig downloaded into the workspace directory is reused before being downloaded again
+const KUBECONFIG_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
 pub fn kubernetes_config_path(
     workspace_directory: &str,
     kubernetes_cluster_id: &str,
     region: &str,
     spaces_secret_key: &str,
     spaces_access_id: &str,
+    force_refresh: bool,
+) -> Result<String, SimpleError> {
+    // a typo in the region fails here with the valid regions, instead of as a network error
+    let region = region.parse::<DoRegion>()?;
+    let spaces = DoSpace::new(spaces_access_id, spaces_secret_key, region.as_str());
+    kubernetes_config_path_from_storage(
+        &spaces,
+        workspace_directory,
+        kubernetes_cluster_id,
+        force_refresh,
+    )
+}
+
+/// download the kubeconfig of the cluster from Spaces into the workspace directory, and call `f`
+/// with the kubeconfig path. The kubeconfig is removed once `f` returns, even if it panics.
+/// `kubernetes_cluster_id` is the Qovery cluster id (`Kubernetes::id()`), not the DOKS cluster name.
+/// ```no_run
+/// use qovery_engine::cloud_provider::digitalocean::common::with_cluster_kubeconfig;
+/// use qovery_engine::cmd::kubectl::kubectl;
+///
+/// let pods = with_cluster_kubeconfig(
+///     "spaces-access-id", "spaces-secret-key", "z1234", "fra1", "/tmp/workspace",
+///     |kubeconfig| kubectl(kubeconfig, Some("default"), &["get", "pods"]),
+/// );
+/// ```
+pub fn with_cluster_kubeconfig<F, R>(
+    spaces_access_id: &str,
+    spaces_secret_key: &str,
+    kubernetes_cluster_id: &str,
+    region: &str,
+    workspace_directory: &str,
+    f: F,
+) -> Result<R, SimpleError>
+where
+    F: FnOnce(&Path) -> R,
+{
+    let region = region.parse::<DoRegion>()?;
+    let spaces = DoSpace::new(spaces_access_id, spaces_secret_key, region.as_str());
+    with_cluster_kubeconfig_from_storage(&spaces, workspace_directory, kubernetes_cluster_id, f)
+}
+
+/// same as `with_cluster_kubeconfig`, with the kubeconfig downloaded from the given object storage
+pub fn with_cluster_kubeconfig_from_storage<F, R>(
+    object_storage: &dyn ObjectStorage,
+    workspace_directory: &str,
+    kubernetes_cluster_id: &str,
+    f: F,
+) -> Result<R, SimpleError>
+where
+    F: FnOnce(&Path) -> R,
+{
+    let kubeconfig_path = kubernetes_config_path_from_storage(
+        object_storage,
+        workspace_directory,
+        kubernetes_cluster_id,
+        false,
+    )?;
+    let kubeconfig = KubeconfigGuard::new(kubeconfig_path);
+
+    Ok(f(kubeconfig.path()))
+}
+
+/// download the kubeconfig of the cluster from the given object storage into the workspace directory.
+/// It fails with `SimpleErrorKind::NotFound` if the kubeconfig is not uploaded yet (worth a retry)
+/// and with `SimpleErrorKind::BucketNotFound` if the bucket itself does not exist.
+/// A kubeconfig downloaded less than `KUBECONFIG_CACHE_TTL` ago is reused, unless `force_refresh` is set.
+pub fn kubernetes_config_path_from_storage(
+    object_storage: &dyn ObjectStorage,
+    workspace_directory: &str,
+    kubernetes_cluster_id: &str,
+    force_refresh: bool,
 ) -> Result<String, SimpleError> {
     let kubernetes_config_bucket_name = format!("qovery-kubeconfigs-{}", kubernetes_cluster_id);
     let kubernetes_config_object_key = format!("{}.yaml", kubernetes_cluster_id);
@@ -23,21 +102,73 @@ pub fn kubernetes_config_path(
         workspace_directory, kubernetes_cluster_id
     );
 
-    let kubeconfig = download_space_object(
-        spaces_access_id,
-        spaces_secret_key,
+    let cached_kubeconfig = Path::new(&kubernetes_config_file_path);
+    if !force_refresh && is_fresh_file(cached_kubeconfig, KUBECONFIG_CACHE_TTL) {
+        return absolute_readable_path(kubernetes_config_file_path.as_str());
+    }
+
+    let downloaded = object_storage.get_to_file(
         kubernetes_config_bucket_name.as_str(),
         kubernetes_config_object_key.as_str(),
-        region,
+        cached_kubeconfig,
     );
-    match kubeconfig {
-        Ok(body) => {
-            let mut file =
-                File::create(kubernetes_config_file_path.clone()).expect("unable to create file");
-            file.write_all(body.as_bytes()).expect("unable to write");
-            Ok(kubernetes_config_file_path)
+    let validated = downloaded
+        .and_then(|_| fs::read_to_string(cached_kubeconfig).map_err(SimpleError::from))
+        .and_then(|content| validate_kubeconfig(content.as_str()));
+    match validated {
+        Ok(_) => absolute_readable_path(kubernetes_config_file_path.as_str()),
+        Err(e) => {
+            // do not leave a partial or invalid kubeconfig behind, it would be taken for a cached one
+            let _ = fs::remove_file(cached_kubeconfig);
+            Err(e)
         }
-        Err(e) => Err(e),
+    }
+}
+
+/// whether the file exists, is not empty and was modified less than `ttl` ago
+fn is_fresh_file(path: &Path, ttl: Duration) -> bool {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+
+    let age = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+    match age {
+        Some(age) => metadata.len() > 0 && age < ttl,
+        None => false,
+    }
+}
+
+/// canonicalize the path, so it still points to the same file if the current directory changes,
+/// and make sure the file can be read
+fn absolute_readable_path(path: &str) -> Result<String, SimpleError> {
+    let absolute_path = match fs::canonicalize(path) {
+        Ok(absolute_path) => absolute_path,
+        Err(e) => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("unable to get the absolute path of {}: {}", path, e)),
+            ))
+        }
+    };
+
+    if let Err(e) = File::open(&absolute_path) {
+        return Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("{} is not readable: {}", absolute_path.display(), e)),
+        ));
+    }
+
+    match absolute_path.to_str() {
+        Some(absolute_path) => Ok(absolute_path.to_string()),
+        None => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("{} is not a valid UTF-8 path", absolute_path.display())),
+        )),
     }
 }
 
@@ -54,60 +185,231 @@ struct Cluster {
 }
 */
 
+/// like the functions below, it goes through `DigitalOceanClient::shared` to reuse one client by token
 pub fn get_uuid_of_cluster(token: &str, kubeID: &str) -> Result<String, SimpleError> {
-    let mut headers = get_header_with_bearer(token);
-    let res = reqwest::blocking::Client::new()
-        .get(do_cluster_api_path)
-        .headers(headers)
-        .send();
-    match res {
-        Ok(response) => match response.status() {
-            StatusCode::OK => {
-                let content = response.text().unwrap();
-                let res_clusters  = serde_json::from_str::<Clusters>(&content);
-                match res_clusters{
-                    Ok(clusters) => match search_uuid_cluster_for(kubeID,clusters){
-                        Some(uuid) => return Ok(uuid),
-                        None => return Err(SimpleError::new(
-                            SimpleErrorKind::Other,
-                            Some(
-                                "Unable to retrieve cluster id from this name",
-                            ),
-                        ))
-                    }
-                    Err(e) => {
-                        print!("{}", e);
-                        return Err(SimpleError::new(
-                            SimpleErrorKind::Other,
-                            Some(
-                                "While trying to deserialize json received from Digital Ocean API",
-                            ),
-                        ));
-                    },
-                }
+    DigitalOceanClient::shared(token)?.get_uuid_of_cluster(kubeID)
+}
+
+pub fn get_cluster_name_for(token: &str, cluster_uuid: &str) -> Result<String, SimpleError> {
+    DigitalOceanClient::shared(token)?.get_cluster_name_for(cluster_uuid)
+}
+
+pub fn get_cluster_status(token: &str, cluster_uuid: &str) -> Result<ClusterStatus, SimpleError> {
+    DigitalOceanClient::shared(token)?.get_cluster_status(cluster_uuid)
+}
+
+pub fn delete_cluster(token: &str, cluster_uuid: &str) -> Result<(), SimpleError> {
+    DigitalOceanClient::shared(token)?.delete_cluster(cluster_uuid)
+}
+
+pub fn resolve_cluster_id_eventually(
+    token: &str,
+    kube_name: &str,
+    timeout: Duration,
+) -> Result<String, SimpleError> {
+    DigitalOceanClient::shared(token)?.resolve_cluster_id_eventually(kube_name, timeout)
+}
+
+pub fn wait_for_cluster_running(
+    token: &str,
+    cluster_uuid: &str,
+    timeout: Duration,
+    poll_interval: Duration,
+    jitter: f64,
+) -> Result<(), SimpleError> {
+    DigitalOceanClient::shared(token)?.wait_for_cluster_running(
+        cluster_uuid,
+        timeout,
+        poll_interval,
+        jitter,
+    )
+}
+
+pub fn wait_for_cluster_ready(
+    token: &str,
+    cluster_uuid: &str,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<(), SimpleError> {
+    DigitalOceanClient::shared(token)?.wait_for_cluster_ready(cluster_uuid, timeout, poll_interval)
+}
+
+pub fn list_regions(token: &str) -> Result<Vec<RegionInfo>, SimpleError> {
+    DigitalOceanClient::shared(token)?.list_regions()
+}
+
+pub fn find_clusters_by_tag(token: &str, tag: &str) -> Result<Vec<ClusterInfo>, SimpleError> {
+    DigitalOceanClient::shared(token)?.find_clusters_by_tag(tag)
+}
+
+pub fn fetch_all_kubeconfigs(
+    token: &str,
+    dest_dir: &Path,
+) -> Result<Vec<(ClusterId, PathBuf)>, SimpleError> {
+    DigitalOceanClient::shared(token)?.fetch_all_kubeconfigs(dest_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud_provider::digitalocean::common::{
+        kubernetes_config_path, kubernetes_config_path_from_storage,
+        with_cluster_kubeconfig_from_storage,
+    };
+    use crate::error::SimpleError;
+    use crate::object_storage::{FileContent, ObjectStorage};
+    use std::cell::{Cell, RefCell};
+    use std::fs;
+
+    struct CountingStorage {
+        gets: Cell<usize>,
+        requested: RefCell<Vec<(String, String)>>,
+        invalid: bool,
+    }
+
+    impl CountingStorage {
+        fn new(invalid: bool) -> Self {
+            CountingStorage {
+                gets: Cell::new(0),
+                requested: RefCell::new(vec![]),
+                invalid,
             }
-            _ => return Err(SimpleError::new(
-                SimpleErrorKind::Other,
-                Some(
-                    "Receive weird status Code from Digital Ocean while retrieving the cluster list",
-                ),
-            )),
-        },
-        Err(_) => {
-            return Err(SimpleError::new(
-                SimpleErrorKind::Other,
-                Some("Unable to get any responses from Digital Ocean"),
-            ))
         }
     }
-}
 
-fn search_uuid_cluster_for(kubeName: &str, clusters: Clusters) -> Option<String> {
-    for cluster in clusters.kubernetes_clusters {
-        match cluster.name.eq(kubeName) {
-            true => return Some(cluster.id),
-            _ => {}
+    impl ObjectStorage for CountingStorage {
+        fn get(&self, bucket_name: &str, object_key: &str) -> Result<FileContent, SimpleError> {
+            self.gets.set(self.gets.get() + 1);
+            self.requested
+                .borrow_mut()
+                .push((bucket_name.to_string(), object_key.to_string()));
+            if self.invalid {
+                return Ok("<html><body>503 Service Unavailable</body></html>".to_string());
+            }
+
+            Ok(format!(
+                "apiVersion: v1\n\
+                current-context: ctx-{}\n\
+                clusters:\n- name: cluster\n  cluster:\n    server: https://k8s.example.com\n\
+                contexts:\n- name: ctx\n  context:\n    cluster: cluster\n    user: admin\n",
+                self.gets.get()
+            ))
+        }
+
+        fn put(
+            &self,
+            _bucket_name: &str,
+            _object_key: &str,
+            _body: &[u8],
+        ) -> Result<(), SimpleError> {
+            Ok(())
+        }
+
+        fn delete(&self, _bucket_name: &str, _object_key: &str) -> Result<(), SimpleError> {
+            Ok(())
         }
+
+        fn exists(&self, _bucket_name: &str, _object_key: &str) -> Result<bool, SimpleError> {
+            Ok(true)
+        }
+
+        fn list(&self, _bucket_name: &str) -> Result<Vec<String>, SimpleError> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_kubeconfig_cache() {
+        let workspace =
+            std::env::temp_dir().join(format!("qovery-kubeconfig-cache-{}", std::process::id()));
+        fs::create_dir_all(&workspace).unwrap();
+        let workspace_directory = workspace.to_str().unwrap();
+        let storage = CountingStorage::new(false);
+
+        let path =
+            kubernetes_config_path_from_storage(&storage, workspace_directory, "z1234", false)
+                .unwrap();
+        let cached =
+            kubernetes_config_path_from_storage(&storage, workspace_directory, "z1234", false)
+                .unwrap();
+        assert_eq!(path, cached);
+        assert_eq!(storage.gets.get(), 1);
+        assert!(fs::read_to_string(&path).unwrap().contains("ctx-1"));
+
+        kubernetes_config_path_from_storage(&storage, workspace_directory, "z1234", true).unwrap();
+        assert_eq!(storage.gets.get(), 2);
+        assert!(fs::read_to_string(&path).unwrap().contains("ctx-2"));
+
+        // an empty file is not a valid cache
+        fs::write(&path, "").unwrap();
+        kubernetes_config_path_from_storage(&storage, workspace_directory, "z1234", false).unwrap();
+        assert_eq!(storage.gets.get(), 3);
+
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn test_kubeconfig_in_missing_workspace_directory() {
+        let storage = CountingStorage::new(false);
+
+        let result = kubernetes_config_path_from_storage(
+            &storage,
+            "/nonexistent/qovery-workspace",
+            "z1234",
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_kubeconfig_is_not_kept() {
+        let workspace =
+            std::env::temp_dir().join(format!("qovery-kubeconfig-invalid-{}", std::process::id()));
+        fs::create_dir_all(&workspace).unwrap();
+        let workspace_directory = workspace.to_str().unwrap();
+        let storage = CountingStorage::new(true);
+
+        let result =
+            kubernetes_config_path_from_storage(&storage, workspace_directory, "z1234", false);
+        assert!(result.is_err());
+        assert!(!workspace.join("kubernetes_config_z1234").exists());
+
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn test_with_cluster_kubeconfig() {
+        let workspace =
+            std::env::temp_dir().join(format!("qovery-kubeconfig-guard-{}", std::process::id()));
+        fs::create_dir_all(&workspace).unwrap();
+        let storage = CountingStorage::new(false);
+
+        let kubeconfig = with_cluster_kubeconfig_from_storage(
+            &storage,
+            workspace.to_str().unwrap(),
+            "z1234",
+            |kubeconfig| kubeconfig.to_path_buf(),
+        )
+        .unwrap();
+        assert_eq!(
+            storage.requested.borrow().as_slice(),
+            &[(
+                "qovery-kubeconfigs-z1234".to_string(),
+                "z1234.yaml".to_string()
+            )]
+        );
+        assert!(kubeconfig.ends_with("kubernetes_config_z1234"));
+        assert!(!kubeconfig.exists());
+
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn test_kubeconfig_with_unknown_region() {
+        let err = kubernetes_config_path("/tmp", "z1234", "nyc", "secret", "access", false)
+            .unwrap_err();
+        assert!(err
+            .message
+            .unwrap()
+            .starts_with("nyc is not a Digital Ocean region"));
     }
-    None
 }
@@ -1,6 +1,6 @@
 use crate::cmd::utilities;
+use crate::proxy::{with_proxy_envs, ProxyConfig};
 use serde::{Deserialize, Serialize};
-use std::borrow::Borrow;
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,23 +40,19 @@ fn get_forbidden_cidr_per_region() -> HashMap<&'static str, &'static str, Random
 }
 
 pub fn get_used_cidr_on_region(token: &str) {
-    let mut output_from_cli = String::new();
-    utilities::exec_with_output(
+    let proxy_envs = ProxyConfig::from_env().envs();
+    let output_from_cli = match utilities::exec_capture(
         "doctl",
         vec!["vpcs", "list", "--output", "json", "-t", token],
-        |r_out| match r_out {
-            Ok(s) => output_from_cli.push_str(&s.to_owned()),
-            Err(e) => error!("DOCTL Cli not respond well{}", e),
-        },
-        |r_err| match r_err {
-            Ok(s) => error!("DOCTL Cli error from cmd inserted, please check vpcs list command{}",
-                s
-            ),
-            Err(e) => error!("DOCTL Cli not respond good {}", e),
-        },
-    );
-    let buff = output_from_cli.borrow();
-    let array: Vec<DoVpc> = serde_json::from_str(&buff).expect("JSON was not well-formatted");
+        with_proxy_envs(vec![], &proxy_envs),
+    ) {
+        Ok(output) => output.stdout,
+        Err(e) => {
+            error!("DOCTL Cli not respond well {}", e.message.unwrap_or_default());
+            return;
+        }
+    };
+    let array: Vec<DoVpc> = serde_json::from_str(&output_from_cli).expect("JSON was not well-formatted");
     for elem in array.iter() {
         let reg = &elem.region;
         let ip = &elem.ip_range;
@@ -24,6 +24,7 @@ use tera::Context as TeraContext;
 
 pub mod cidr;
 pub mod node;
+pub mod node_pool;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Options {
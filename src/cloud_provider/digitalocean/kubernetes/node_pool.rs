@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cloud_provider::digitalocean::api_structs::clusters::NodePool;
+
+/// desired configuration of a node pool, pools are matched with the cluster ones by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodePoolSpec {
+    pub name: String,
+    /// droplet size of the nodes (ex: "s-2vcpu-4gb")
+    pub size: String,
+    pub count: i64,
+    pub auto_scale: bool,
+    pub min_nodes: i64,
+    pub max_nodes: i64,
+}
+
+/// a pool to resize in place, its droplet size stays the same
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodePoolResize {
+    /// id of the existing pool
+    pub id: String,
+    pub current: NodePoolSpec,
+    pub desired: NodePoolSpec,
+}
+
+/// what to change on the cluster node pools to reach the desired ones
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct NodePoolDiff {
+    pub to_add: Vec<NodePoolSpec>,
+    /// ids of the pools to remove
+    pub to_remove: Vec<String>,
+    pub to_resize: Vec<NodePoolResize>,
+}
+
+impl NodePoolDiff {
+    pub fn is_empty(&self) -> bool {
+        self.to_add.is_empty() && self.to_remove.is_empty() && self.to_resize.is_empty()
+    }
+}
+
+impl From<&NodePool> for NodePoolSpec {
+    fn from(pool: &NodePool) -> Self {
+        NodePoolSpec {
+            name: pool.name.clone(),
+            size: pool.size.clone(),
+            count: pool.count,
+            auto_scale: pool.auto_scale,
+            min_nodes: pool.min_nodes,
+            max_nodes: pool.max_nodes,
+        }
+    }
+}
+
+/// compare the desired node pools with the actual ones of the cluster.
+/// The droplet size of a pool can't be changed, so a pool with another size is removed and added again.
+/// The node count of a pool auto-scaled on both sides belongs to the autoscaler and is not compared.
+pub fn node_pool_diff(desired: &[NodePoolSpec], actual: &[NodePool]) -> NodePoolDiff {
+    let mut diff = NodePoolDiff::default();
+
+    for pool in actual {
+        match desired.iter().find(|spec| spec.name == pool.name) {
+            None => diff.to_remove.push(pool.id.clone()),
+            Some(spec) if spec.size != pool.size => {
+                diff.to_remove.push(pool.id.clone());
+                diff.to_add.push(spec.clone());
+            }
+            Some(spec) => {
+                let current = NodePoolSpec::from(pool);
+                if needs_resize(&current, spec) {
+                    diff.to_resize.push(NodePoolResize {
+                        id: pool.id.clone(),
+                        current,
+                        desired: spec.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    diff.to_add.extend(
+        desired
+            .iter()
+            .filter(|spec| !actual.iter().any(|pool| pool.name == spec.name))
+            .cloned(),
+    );
+
+    diff
+}
+
+fn needs_resize(current: &NodePoolSpec, desired: &NodePoolSpec) -> bool {
+    if current.auto_scale != desired.auto_scale {
+        return true;
+    }
+
+    match desired.auto_scale {
+        true => current.min_nodes != desired.min_nodes || current.max_nodes != desired.max_nodes,
+        false => current.count != desired.count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud_provider::digitalocean::api_structs::clusters::NodePool;
+    use crate::cloud_provider::digitalocean::kubernetes::node_pool::{
+        node_pool_diff, NodePoolSpec,
+    };
+
+    fn spec(name: &str, size: &str, count: i64, auto_scale: bool) -> NodePoolSpec {
+        NodePoolSpec {
+            name: name.to_string(),
+            size: size.to_string(),
+            count,
+            auto_scale,
+            min_nodes: 1,
+            max_nodes: 5,
+        }
+    }
+
+    fn pool(id: &str, spec: NodePoolSpec) -> NodePool {
+        NodePool {
+            id: id.to_string(),
+            name: spec.name,
+            size: spec.size,
+            count: spec.count,
+            auto_scale: spec.auto_scale,
+            min_nodes: spec.min_nodes,
+            max_nodes: spec.max_nodes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_node_pool_diff() {
+        let actual = vec![
+            pool("1", spec("unchanged", "s-2vcpu-4gb", 3, false)),
+            pool("2", spec("resized", "s-2vcpu-4gb", 3, false)),
+            pool("3", spec("autoscaled", "s-2vcpu-4gb", 4, true)),
+            pool("4", spec("bigger", "s-2vcpu-4gb", 3, false)),
+            pool("5", spec("removed", "s-2vcpu-4gb", 3, false)),
+        ];
+        let desired = vec![
+            spec("unchanged", "s-2vcpu-4gb", 3, false),
+            spec("resized", "s-2vcpu-4gb", 5, false),
+            spec("autoscaled", "s-2vcpu-4gb", 2, true),
+            spec("bigger", "s-4vcpu-8gb", 3, false),
+            spec("added", "s-2vcpu-4gb", 2, false),
+        ];
+
+        let diff = node_pool_diff(&desired, &actual);
+
+        assert_eq!(diff.to_remove, vec!["4", "5"]);
+        assert_eq!(
+            diff.to_add,
+            vec![
+                spec("bigger", "s-4vcpu-8gb", 3, false),
+                spec("added", "s-2vcpu-4gb", 2, false),
+            ]
+        );
+        assert_eq!(diff.to_resize.len(), 1);
+        assert_eq!(diff.to_resize[0].id, "2");
+        assert_eq!(diff.to_resize[0].current.count, 3);
+        assert_eq!(diff.to_resize[0].desired.count, 5);
+
+        assert!(node_pool_diff(&desired[..1], &actual[..1]).is_empty());
+    }
+}
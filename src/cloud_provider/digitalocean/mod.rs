@@ -1,8 +1,10 @@
 pub mod api_structs;
 pub mod application;
+pub mod client;
 pub mod common;
 pub mod databases;
 pub mod kubernetes;
+pub mod region;
 
 extern crate digitalocean;
 
@@ -11,8 +13,9 @@ use std::rc::Rc;
 
 use digitalocean::DigitalOcean;
 
+use crate::cloud_provider::digitalocean::client::DigitalOceanClient;
 use crate::cloud_provider::{CloudProvider, Kind, TerraformStateCredentials};
-use crate::error::{EngineError, EngineErrorCause};
+use crate::error::{EngineError, EngineErrorCause, SimpleError};
 use crate::models::{Context, Listener, Listeners, ProgressListener};
 
 pub struct DO {
@@ -25,6 +28,7 @@ pub struct DO {
     terraform_state_credentials: TerraformStateCredentials,
     listeners: Listeners,
     region: String,
+    api_client: DigitalOceanClient,
 }
 
 impl DO {
@@ -37,8 +41,8 @@ impl DO {
         spaces_secret_key: &str,
         name: &str,
         terraform_state_credentials: TerraformStateCredentials,
-    ) -> Self {
-        DO {
+    ) -> Result<Self, SimpleError> {
+        Ok(DO {
             context,
             id: id.to_string(),
             name: name.to_string(),
@@ -48,12 +52,18 @@ impl DO {
             spaces_secret_key: spaces_secret_key.to_string(),
             terraform_state_credentials,
             listeners: vec![],
-        }
+            api_client: DigitalOceanClient::new(token)?,
+        })
     }
 
     pub fn client(&self) -> DigitalOcean {
         DigitalOcean::new(self.token.as_str()).unwrap()
     }
+
+    /// DigitalOcean API client shared by every call made for this cloud provider
+    pub fn api_client(&self) -> &DigitalOceanClient {
+        &self.api_client
+    }
 }
 
 impl CloudProvider for DO {
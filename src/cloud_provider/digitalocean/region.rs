@@ -0,0 +1,139 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{SimpleError, SimpleErrorKind};
+
+/// DigitalOcean region, written as its slug (ex: "fra1")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DoRegion {
+    Nyc1,
+    Nyc2,
+    Nyc3,
+    Ams2,
+    Ams3,
+    Sfo1,
+    Sfo2,
+    Sfo3,
+    Sgp1,
+    Lon1,
+    Fra1,
+    Tor1,
+    Blr1,
+    Syd1,
+}
+
+impl DoRegion {
+    pub const ALL: [DoRegion; 14] = [
+        DoRegion::Nyc1,
+        DoRegion::Nyc2,
+        DoRegion::Nyc3,
+        DoRegion::Ams2,
+        DoRegion::Ams3,
+        DoRegion::Sfo1,
+        DoRegion::Sfo2,
+        DoRegion::Sfo3,
+        DoRegion::Sgp1,
+        DoRegion::Lon1,
+        DoRegion::Fra1,
+        DoRegion::Tor1,
+        DoRegion::Blr1,
+        DoRegion::Syd1,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DoRegion::Nyc1 => "nyc1",
+            DoRegion::Nyc2 => "nyc2",
+            DoRegion::Nyc3 => "nyc3",
+            DoRegion::Ams2 => "ams2",
+            DoRegion::Ams3 => "ams3",
+            DoRegion::Sfo1 => "sfo1",
+            DoRegion::Sfo2 => "sfo2",
+            DoRegion::Sfo3 => "sfo3",
+            DoRegion::Sgp1 => "sgp1",
+            DoRegion::Lon1 => "lon1",
+            DoRegion::Fra1 => "fra1",
+            DoRegion::Tor1 => "tor1",
+            DoRegion::Blr1 => "blr1",
+            DoRegion::Syd1 => "syd1",
+        }
+    }
+
+    /// whether Spaces (the object storage) is available in the region
+    pub fn has_spaces(&self) -> bool {
+        matches!(
+            self,
+            DoRegion::Ams3
+                | DoRegion::Fra1
+                | DoRegion::Nyc3
+                | DoRegion::Sfo2
+                | DoRegion::Sfo3
+                | DoRegion::Sgp1
+        )
+    }
+
+    pub fn spaces_regions() -> Vec<DoRegion> {
+        DoRegion::ALL
+            .iter()
+            .copied()
+            .filter(|x| x.has_spaces())
+            .collect()
+    }
+}
+
+impl fmt::Display for DoRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DoRegion {
+    type Err = SimpleError;
+
+    /// the slug is case insensitive, "FRA1" is `DoRegion::Fra1`
+    fn from_str(region: &str) -> Result<Self, Self::Err> {
+        let slug = region.trim().to_lowercase();
+
+        match DoRegion::ALL.iter().find(|x| x.as_str() == slug) {
+            Some(region) => Ok(*region),
+            None => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "{} is not a Digital Ocean region (valid regions: {})",
+                    region,
+                    DoRegion::ALL
+                        .iter()
+                        .map(|x| x.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud_provider::digitalocean::region::DoRegion;
+
+    #[test]
+    fn test_do_region() {
+        assert_eq!("fra1".parse::<DoRegion>().unwrap(), DoRegion::Fra1);
+        assert_eq!(" NYC3 ".parse::<DoRegion>().unwrap(), DoRegion::Nyc3);
+        assert_eq!(DoRegion::Sgp1.to_string(), "sgp1");
+
+        let err = "nyc".parse::<DoRegion>().unwrap_err();
+        assert!(err
+            .message
+            .unwrap()
+            .starts_with("nyc is not a Digital Ocean region (valid regions: nyc1, nyc2, nyc3,"));
+
+        for region in DoRegion::ALL.iter() {
+            assert_eq!(region.as_str().parse::<DoRegion>().unwrap(), *region);
+        }
+        assert_eq!(DoRegion::spaces_regions().len(), 6);
+    }
+}
@@ -0,0 +1,269 @@
+//! async versions of `exec` and `exec_with_output`, for callers already running on a tokio runtime
+//! which would otherwise need `spawn_blocking`. It needs a runtime with the IO driver enabled.
+
+use std::collections::VecDeque;
+use std::io::Error;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::process::Child;
+
+use crate::cmd::output::{OutputLine, Stream};
+use crate::cmd::runner::command_runner;
+use crate::cmd::utilities::{
+    binary_name, check_output, command, command_error, command_to_string, current_dir_of,
+    log_command, push_stderr_tail, record_command_metrics, spawn_failed, CommandOutput,
+    COMMAND_ERROR_STDERR_MAX_LINES,
+};
+use crate::error::SimpleError;
+
+/// same as `exec_capture` without envs: the whole stdout and stderr are captured,
+/// and attached to the error (`SimpleError::output`) if the command fails.
+/// Dropping the future before it completes kills the command.
+pub async fn exec_async<P>(binary: P, args: Vec<&str>) -> Result<CommandOutput, SimpleError>
+where
+    P: AsRef<Path>,
+{
+    let command_string = command_to_string(binary.as_ref(), &args);
+    let binary_name = binary_name(binary.as_ref());
+    let cmd = command(binary, args, None, None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    let started_at = Instant::now();
+    let result = capture_async(cmd).await;
+    record_command_metrics(binary_name.as_str(), started_at.elapsed(), &result);
+
+    result
+}
+
+/// same as `exec_with_output`: each stdout and stderr line is handed to its callback as soon as
+/// the command writes it, and the last stderr lines are part of the error if the command fails.
+/// Dropping the future before it completes kills the command.
+pub async fn exec_with_output_async<P, F, X>(
+    binary: P,
+    args: Vec<&str>,
+    stdout_output: F,
+    stderr_output: X,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    F: FnMut(Result<String, Error>),
+    X: FnMut(Result<String, Error>),
+{
+    let command_string = command_to_string(binary.as_ref(), &args);
+    let binary_name = binary_name(binary.as_ref());
+    let cmd = command(binary, args, None, None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    let started_at = Instant::now();
+    let result = with_output_async(cmd, stdout_output, stderr_output).await;
+    record_command_metrics(binary_name.as_str(), started_at.elapsed(), &result);
+
+    result
+}
+
+async fn capture_async(mut cmd: Command) -> Result<CommandOutput, SimpleError> {
+    if let Some(runner) = command_runner() {
+        return check_output(runner.run(&mut cmd)?);
+    }
+
+    let current_dir = current_dir_of(&cmd);
+    let mut child = spawn_async(cmd)?;
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    forward_lines(&mut child, |line| {
+        let output = match line.stream {
+            Stream::Stdout => &mut stdout,
+            Stream::Stderr => &mut stderr,
+        };
+        output.push_str(line.text.as_str());
+        output.push('\n');
+    })
+    .await;
+
+    check_output(CommandOutput {
+        stdout,
+        stderr,
+        status: (&mut child).await?,
+        current_dir,
+        phases: vec![],
+        envs: vec![],
+    })
+}
+
+async fn with_output_async<F, X>(
+    mut cmd: Command,
+    mut stdout_output: F,
+    mut stderr_output: X,
+) -> Result<(), SimpleError>
+where
+    F: FnMut(Result<String, Error>),
+    X: FnMut(Result<String, Error>),
+{
+    if let Some(runner) = command_runner() {
+        let output = runner.run(&mut cmd)?;
+        output
+            .stdout
+            .lines()
+            .for_each(|x| stdout_output(Ok(x.to_string())));
+        output
+            .stderr
+            .lines()
+            .for_each(|x| stderr_output(Ok(x.to_string())));
+        return check_output(output).map(|_| ());
+    }
+
+    let mut child = spawn_async(cmd)?;
+
+    let mut stderr_tail = VecDeque::with_capacity(COMMAND_ERROR_STDERR_MAX_LINES);
+    forward_lines(&mut child, |line| match line.stream {
+        Stream::Stdout => stdout_output(Ok(line.text)),
+        Stream::Stderr => {
+            push_stderr_tail(&mut stderr_tail, line.text.as_str());
+            stderr_output(Ok(line.text))
+        }
+    })
+    .await;
+
+    let exit_status = (&mut child).await?;
+    if exit_status.success() {
+        return Ok(());
+    }
+
+    Err(command_error(
+        exit_status,
+        Vec::from(stderr_tail).join("\n").as_str(),
+    ))
+}
+
+fn spawn_async(cmd: Command) -> Result<Child, SimpleError> {
+    let binary = binary_name(Path::new(cmd.get_program()));
+
+    tokio::process::Command::from(cmd)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| spawn_failed(binary, err))
+}
+
+/// read stdout and stderr of the child concurrently, until both are closed,
+/// and hand each line to `on_line` as soon as it is read
+async fn forward_lines<F>(child: &mut Child, mut on_line: F)
+where
+    F: FnMut(OutputLine),
+{
+    let mut stdout = child.stdout.take().map(BufReader::new);
+    let mut stderr = child.stderr.take().map(BufReader::new);
+    // a read interrupted by a line of the other stream keeps what it read in its buffer
+    let mut stdout_buffer = vec![];
+    let mut stderr_buffer = vec![];
+
+    loop {
+        let line = tokio::select! {
+            text = next_line(&mut stdout, &mut stdout_buffer, Stream::Stdout), if stdout.is_some() => {
+                text.map(|text| OutputLine::new(Stream::Stdout, text))
+            }
+            text = next_line(&mut stderr, &mut stderr_buffer, Stream::Stderr), if stderr.is_some() => {
+                text.map(|text| OutputLine::new(Stream::Stderr, text))
+            }
+            else => break,
+        };
+
+        if let Some(line) = line {
+            on_line(line);
+        }
+    }
+}
+
+/// next line of the stream, without its line terminator. A last line without trailing newline is
+/// returned too. Once the stream ends (or fails), `reader` is set to `None` and `None` is returned.
+async fn next_line<R>(
+    reader: &mut Option<R>,
+    buffer: &mut Vec<u8>,
+    stream: Stream,
+) -> Option<String>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let result = match reader.as_mut() {
+        Some(reader) => reader.read_until(b'\n', buffer).await,
+        None => return None,
+    };
+
+    match result {
+        Ok(0) if buffer.is_empty() => {
+            *reader = None;
+            None
+        }
+        Ok(_) => {
+            while buffer.last() == Some(&b'\n') || buffer.last() == Some(&b'\r') {
+                buffer.pop();
+            }
+
+            let line = String::from_utf8_lossy(buffer).to_string();
+            buffer.clear();
+            Some(line)
+        }
+        Err(err) => {
+            error!("error while reading the command {:?}: {:?}", stream, err);
+            *reader = None;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::async_utilities::{exec_async, exec_with_output_async};
+    use crate::error::SimpleErrorKind;
+    use crate::runtime::async_run;
+
+    #[test]
+    fn test_exec_async() {
+        let output = async_run(exec_async(
+            "sh",
+            vec!["-c", "echo out; echo err >&2; printf last"],
+        ))
+        .unwrap();
+        assert_eq!(output.stdout, "out\nlast\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(output.status.success());
+
+        let err = async_run(exec_async(
+            "sh",
+            vec!["-c", "echo partial plan; echo locked >&2; exit 1"],
+        ))
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(1)));
+        assert_eq!(err.output.unwrap().stdout, "partial plan\n");
+
+        let err = async_run(exec_async("qovery-nonexistent-binary", vec![])).unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::SpawnFailed { .. }));
+    }
+
+    #[test]
+    fn test_exec_with_output_async() {
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+
+        // more stderr than a pipe holds before stdout: both streams must be read at the same time
+        let result = async_run(exec_with_output_async(
+            "sh",
+            vec![
+                "-c",
+                "i=0; while [ $i -lt 2000 ]; do echo 'a line of stderr output' >&2; i=$((i+1)); done; \
+                echo done; exit 2",
+            ],
+            |line| stdout.push(line.unwrap()),
+            |line| stderr.push(line.unwrap()),
+        ));
+
+        let err = result.unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(2)));
+        assert!(err.message.unwrap().ends_with("a line of stderr output"));
+        assert_eq!(stdout, vec!["done"]);
+        assert_eq!(stderr.len(), 2000);
+    }
+}
@@ -0,0 +1,221 @@
+use std::io;
+use std::io::Read;
+use std::panic;
+use std::process::{Child, ExitStatus, Output};
+use std::thread;
+use std::time::Duration;
+
+/// time given to the child of a dropped guard (a cancelled command) to stop after SIGTERM, before it is killed
+pub const DROP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// interval at which a terminated child is checked for exit during its grace period
+#[cfg(unix)]
+const TERMINATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Owns a spawned child process and makes sure it does not outlive its owner:
+/// if the guard is dropped before the child has been waited on (ex: the task running it is aborted,
+/// or a panic unwinds), the child is terminated (SIGTERM, then SIGKILL after `DROP_GRACE_PERIOD`)
+/// and reaped, so it never keeps running nor becomes a zombie.
+///
+/// Dropping the guard is cancelling the command.
+pub struct ChildGuard {
+    child: Child,
+    reaped: bool,
+}
+
+impl ChildGuard {
+    pub fn new(child: Child) -> Self {
+        ChildGuard {
+            child,
+            reaped: false,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn child_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// wait for the child to exit, the guard then has nothing left to clean up
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        self.reaped = true;
+        Ok(status)
+    }
+
+    /// same as `Child::wait_with_output`: read the child stdout and stderr until they are closed,
+    /// and wait for it to exit
+    pub fn wait_with_output(mut self) -> io::Result<Output> {
+        let stderr_reader = self.child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut buffer = vec![];
+                stderr.read_to_end(&mut buffer).map(|_| buffer)
+            })
+        });
+
+        let mut stdout = vec![];
+        if let Some(mut child_stdout) = self.child.stdout.take() {
+            child_stdout.read_to_end(&mut stdout)?;
+        }
+
+        let stderr = match stderr_reader {
+            Some(reader) => match reader.join() {
+                Ok(stderr) => stderr?,
+                Err(panic) => panic::resume_unwind(panic),
+            },
+            None => vec![],
+        };
+
+        Ok(Output {
+            status: self.wait()?,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// return the exit status if the child has exited, without blocking.
+    /// Once it has exited, the guard has nothing left to clean up.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait()?;
+        if status.is_some() {
+            self.reaped = true;
+        }
+        Ok(status)
+    }
+
+    /// kill the child and reap it
+    pub fn kill(&mut self) -> io::Result<()> {
+        kill_and_reap(&mut self.child)?;
+        self.reaped = true;
+        Ok(())
+    }
+
+    /// stop the child with `terminate_gracefully` and reap it
+    pub fn terminate(&mut self, grace: Duration) -> io::Result<()> {
+        terminate_gracefully(&mut self.child, grace)?;
+        self.reaped = true;
+        Ok(())
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if self.reaped {
+            return;
+        }
+
+        if let Err(err) = terminate_gracefully(&mut self.child, DROP_GRACE_PERIOD) {
+            error!(
+                "unable to terminate the child process {}: {:?}",
+                self.child.id(),
+                err
+            );
+        }
+    }
+}
+
+/// ask the child to stop with SIGTERM, giving it `grace` to clean up (ex: terraform releasing
+/// its state lock), and kill it if it is still running after that. The child is reaped in any case.
+/// It is killed right away on non-unix platforms.
+pub fn terminate_gracefully(child: &mut Child, grace: Duration) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::time::Instant;
+
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+
+        info!("terminating the child process {}", child.id());
+        if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+            return kill_and_reap(child);
+        }
+
+        let started_at = Instant::now();
+        while started_at.elapsed() < grace {
+            if child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(TERMINATE_POLL_INTERVAL.min(grace - started_at.elapsed()));
+        }
+    }
+
+    kill_and_reap(child)
+}
+
+fn kill_and_reap(child: &mut Child) -> io::Result<()> {
+    // the child may already have exited on its own, it only has to be reaped then
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+
+    info!("killing the child process {}", child.id());
+    child.kill()?;
+    child.wait().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::child::{terminate_gracefully, ChildGuard};
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_drop_kills_and_reaps_the_child() {
+        let guard = ChildGuard::new(Command::new("sleep").arg("30").spawn().unwrap());
+        let pid = guard.id() as libc::pid_t;
+
+        drop(guard);
+
+        // the process does not exist anymore, not even as a zombie
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_terminate_kills_after_the_grace_period() {
+        let mut guard = ChildGuard::new(
+            Command::new("sh")
+                .args(["-c", "trap '' TERM; sleep 30"])
+                .spawn()
+                .unwrap(),
+        );
+        let pid = guard.id() as libc::pid_t;
+        // let the shell install its trap
+        std::thread::sleep(Duration::from_millis(200));
+
+        let started_at = Instant::now();
+        guard.terminate(Duration::from_millis(300)).unwrap();
+
+        assert!(started_at.elapsed() >= Duration::from_millis(300));
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_terminate_gracefully_lets_the_child_clean_up() {
+        let marker = std::env::temp_dir().join(format!("qovery-terminated-{}", std::process::id()));
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                &format!(
+                    "trap 'touch {}; exit 0' TERM; sleep 30 & wait",
+                    marker.display()
+                ),
+            ])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let started_at = Instant::now();
+        terminate_gracefully(&mut child, Duration::from_secs(5)).unwrap();
+
+        // the child exited on SIGTERM, without waiting for the grace period
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert!(marker.exists());
+        std::fs::remove_file(&marker).unwrap();
+    }
+}
@@ -0,0 +1,971 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::cmd::deadline::Deadline;
+use crate::cmd::output::{OutputLine, OutputSink, PhaseTracker, ProgressReporter, Stream};
+use crate::cmd::runner::{dry_run_output, is_dry_run, thread_command_runner};
+use crate::cmd::utilities::{
+    command, command_with_envs_to_string, is_sensitive_env_key, log_command, run_and_capture,
+    run_and_capture_lines, CommandOutput,
+};
+use crate::constants::ENGINE_TASK;
+use crate::error::{SimpleError, SimpleErrorKind};
+use crate::string::redact_secrets;
+use regex::Regex;
+use serde::Serialize;
+
+/// maximum number of characters of stdout and stderr in the JSON log of a command
+const JSON_LOG_OUTPUT_MAX_CHARS: usize = 4096;
+
+/// Build and run a command step by step
+/// ```no_run
+/// use qovery_engine::cmd::command::CommandBuilder;
+/// use std::time::Duration;
+///
+/// let output = CommandBuilder::new("terraform")
+///     .args(vec!["plan", "-out", "tf_plan"])
+///     .env("TF_IN_AUTOMATION", "true")
+///     .current_dir("/tmp/my-module")
+///     .timeout(Duration::from_secs(600))
+///     .on_stdout(|line| println!("{}", line))
+///     .run();
+/// ```
+pub struct CommandBuilder {
+    binary: PathBuf,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+    niceness: Option<i32>,
+    expand_env_vars: bool,
+    fail_on_stderr: bool,
+    phases: Vec<(String, String)>,
+    map_line: Option<Box<dyn FnMut(OutputLine) -> Option<OutputLine>>>,
+    log_lines: bool,
+    deadline: Option<Deadline>,
+    timeout: Option<Duration>,
+    stdin: Option<Vec<u8>>,
+    dry_run: bool,
+    secrets: Vec<String>,
+    log_json: bool,
+    recorded_envs: Option<Vec<String>>,
+    sinks: Vec<Box<dyn OutputSink>>,
+    progress: Option<ProgressReporter<'static>>,
+}
+
+/// calls `callback` with the text of the lines of one stream, see `CommandBuilder::on_stdout`
+struct StreamCallback {
+    stream: Stream,
+    callback: Box<dyn FnMut(&str)>,
+}
+
+impl OutputSink for StreamCallback {
+    fn on_line(&mut self, line: &OutputLine) {
+        if line.stream == self.stream {
+            (self.callback)(line.text.as_str())
+        }
+    }
+}
+
+/// command result logged as a single JSON line by `CommandBuilder::log_json`
+#[derive(Serialize)]
+struct CommandLog<'a> {
+    binary: String,
+    args: Vec<String>,
+    /// null if the command did not exit by itself (not started, killed...)
+    exit_code: Option<i32>,
+    duration_seconds: f64,
+    /// last characters of the output
+    stdout: Option<String>,
+    stderr: Option<String>,
+    error: Option<&'a str>,
+}
+
+impl CommandBuilder {
+    pub fn new<P: AsRef<Path>>(binary: P) -> Self {
+        CommandBuilder {
+            binary: binary.as_ref().to_path_buf(),
+            args: vec![],
+            envs: vec![],
+            current_dir: None,
+            niceness: None,
+            expand_env_vars: false,
+            fail_on_stderr: false,
+            phases: vec![],
+            map_line: None,
+            log_lines: false,
+            deadline: None,
+            timeout: None,
+            stdin: None,
+            dry_run: false,
+            secrets: vec![],
+            log_json: false,
+            recorded_envs: None,
+            sinks: vec![],
+            progress: None,
+        }
+    }
+
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<S: Into<String>>(mut self, args: Vec<S>) -> Self {
+        self.args.extend(args.into_iter().map(|x| x.into()));
+        self
+    }
+
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn envs<K: Into<String>, V: Into<String>>(mut self, envs: Vec<(K, V)>) -> Self {
+        self.envs
+            .extend(envs.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(mut self, current_dir: P) -> Self {
+        self.current_dir = Some(current_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// run the command with a lower scheduling priority (from 0 to 19, 19 being the lowest priority)
+    /// so it does not starve other processes on busy hosts. Unset by default.
+    #[cfg(unix)]
+    pub fn niceness(mut self, niceness: i32) -> Self {
+        self.niceness = Some(niceness);
+        self
+    }
+
+    /// expand `${VAR}` tokens in the arguments with the command envs, falling back to the process env.
+    /// No shell is involved, and an unknown variable makes the command fail instead of expanding to nothing.
+    pub fn expand_env_vars(mut self) -> Self {
+        self.expand_env_vars = true;
+        self
+    }
+
+    /// consider the command as failed if it writes anything on stderr, even if it exits with 0
+    pub fn fail_on_stderr(mut self, fail_on_stderr: bool) -> Self {
+        self.fail_on_stderr = fail_on_stderr;
+        self
+    }
+
+    /// start the phase `phase_name` when an output line matches `marker_regex`.
+    /// The time spent in each phase is returned in `CommandOutput::phases`. No phase by default.
+    pub fn phase<R: Into<String>, N: Into<String>>(
+        mut self,
+        marker_regex: R,
+        phase_name: N,
+    ) -> Self {
+        self.phases.push((marker_regex.into(), phase_name.into()));
+        self
+    }
+
+    /// transform each output line before it is logged and captured (ex: to redact or reformat it),
+    /// returning `None` drops the line. Lines are not transformed by default.
+    pub fn map_line<F>(mut self, map_line: F) -> Self
+    where
+        F: FnMut(OutputLine) -> Option<OutputLine> + 'static,
+    {
+        self.map_line = Some(Box::new(map_line));
+        self
+    }
+
+    /// log each output line as it comes, after `map_line` and without the secrets:
+    /// stdout as info and stderr as error. Lines are not logged by default.
+    pub fn log_lines(mut self, log_lines: bool) -> Self {
+        self.log_lines = log_lines;
+        self
+    }
+
+    /// tag the command with the engine task it is run for (ex: "deploy-app-123"), set in its
+    /// `ENGINE_TASK` env var so it can be told apart from the other engine processes on the host
+    /// (ex: with `grep -z ENGINE_TASK /proc/<pid>/environ`). Untagged by default.
+    pub fn task<S: Into<String>>(self, task: S) -> Self {
+        self.env(ENGINE_TASK, task)
+    }
+
+    /// send every output line, after `map_line` and without the secrets, to `sink`
+    /// (ex: `LogSink`, `FileSink`, `ChannelSink`). Can be called several times, each sink gets every line.
+    pub fn sink<S: OutputSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn sinks(mut self, sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        self.sinks.extend(sinks);
+        self
+    }
+
+    /// report the lines read and the time elapsed while the command runs, every line is counted
+    /// before `map_line`. No progress report by default.
+    pub fn on_progress(mut self, progress: ProgressReporter<'static>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// stop the command (SIGTERM, then SIGKILL after `TIMEOUT_GRACE_PERIOD`) and fail with a `Timeout`
+    /// error once the deadline is exceeded.
+    /// Give the same deadline to several commands to bound the whole sequence. No deadline by default.
+    pub fn deadline(mut self, deadline: Option<Deadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// stop the command like `deadline` and fail with a `Timeout` error if it runs for longer than `timeout`,
+    /// counted from `run`. If a deadline is set too, the earliest one applies. No timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// write `input` on the command stdin, which is closed afterwards. No stdin by default.
+    pub fn stdin<I: Into<Vec<u8>>>(mut self, input: I) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    /// log the command line, without the secrets, instead of running the command,
+    /// which then succeeds with an empty output. Also enabled for every command by `set_dry_run`.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// call `on_stdout` with each stdout line, without the secrets, as soon as the command writes it
+    pub fn on_stdout<F: FnMut(&str) + 'static>(self, on_stdout: F) -> Self {
+        self.sink(StreamCallback {
+            stream: Stream::Stdout,
+            callback: Box::new(on_stdout),
+        })
+    }
+
+    /// same as `on_stdout`, for stderr
+    pub fn on_stderr<F: FnMut(&str) + 'static>(self, on_stderr: F) -> Self {
+        self.sink(StreamCallback {
+            stream: Stream::Stderr,
+            callback: Box::new(on_stderr),
+        })
+    }
+
+    /// a secret (ex: a token passed as argument or env var) to replace by `****` in the logged
+    /// command line, output lines and errors. The command itself still gets the real value.
+    pub fn secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.secrets.push(secret.into());
+        self
+    }
+
+    /// once the command is done, log its result as a single JSON line (binary, args, exit code,
+    /// duration, and the end of stdout and stderr), without the secrets. Disabled by default.
+    pub fn log_json(mut self, log_json: bool) -> Self {
+        self.log_json = log_json;
+        self
+    }
+
+    /// record the environment of the command in `CommandOutput::envs`, to be able to reproduce it:
+    /// the envs given to the builder, plus the `inherited` variables of the engine environment
+    /// (ex: "PATH", "KUBECONFIG") if they are set. The secrets are redacted, and the values of the
+    /// sensitive keys (see `is_sensitive_env_key`) masked. Nothing is recorded by default.
+    pub fn record_envs(mut self, inherited: Vec<&str>) -> Self {
+        self.recorded_envs = Some(inherited.into_iter().map(|x| x.to_string()).collect());
+        self
+    }
+
+    fn envs_snapshot(&self, inherited: &[String]) -> Vec<(String, String)> {
+        let mut envs = inherited
+            .iter()
+            .filter(|name| !self.envs.iter().any(|(k, _)| k == *name))
+            .filter_map(|name| env::var(name).ok().map(|value| (name.clone(), value)))
+            .collect::<Vec<_>>();
+        envs.extend(self.envs.iter().cloned());
+
+        envs.into_iter()
+            .map(|(k, v)| match is_sensitive_env_key(k.as_str()) {
+                true => (k, "***".to_string()),
+                false => (k, self.redact(v.as_str())),
+            })
+            .collect()
+    }
+
+    fn redact(&self, value: &str) -> String {
+        redact_secrets(
+            value,
+            &self.secrets.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+        )
+    }
+
+    fn redact_error(&self, err: SimpleError) -> SimpleError {
+        let secrets = self.secrets.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        SimpleError {
+            kind: err.kind,
+            message: err.message.map(|message| self.redact(message.as_str())),
+            output: err.output.map(|output| Box::new(output.redacted(&secrets))),
+            source: err.source,
+        }
+    }
+
+    fn phase_tracker(&self) -> Result<PhaseTracker, SimpleError> {
+        let mut markers = vec![];
+        for (marker_regex, phase_name) in &self.phases {
+            match Regex::new(marker_regex.as_str()) {
+                Ok(marker) => markers.push((marker, phase_name.clone())),
+                Err(err) => {
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(format!(
+                            "invalid marker {} of phase {}: {}",
+                            marker_regex, phase_name, err
+                        )),
+                    ))
+                }
+            }
+        }
+
+        Ok(PhaseTracker::new(markers))
+    }
+
+    fn resolved_args(&self) -> Result<Vec<String>, SimpleError> {
+        if !self.expand_env_vars {
+            return Ok(self.args.clone());
+        }
+
+        self.args
+            .iter()
+            .map(|arg| {
+                expand_env_vars(arg.as_str(), |name| {
+                    self.envs
+                        .iter()
+                        .rev()
+                        .find(|(k, _)| k == name)
+                        .map(|(_, v)| v.clone())
+                        .or_else(|| env::var(name).ok())
+                })
+            })
+            .collect()
+    }
+
+    /// the earliest of the deadline and the timeout, if any
+    fn effective_deadline(&self) -> Option<Deadline> {
+        match (self.deadline, self.timeout.map(Deadline::after)) {
+            (Some(deadline), Some(timeout)) if timeout.instant() < deadline.instant() => {
+                Some(timeout)
+            }
+            (Some(deadline), _) => Some(deadline),
+            (None, timeout) => timeout,
+        }
+    }
+
+    fn command(&self, args: &[String]) -> Result<Command, SimpleError> {
+        let mut cmd = command(
+            self.binary.as_path(),
+            args.iter().map(|x| x.as_str()).collect(),
+            Some(
+                self.envs
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect(),
+            ),
+            self.current_dir.as_deref(),
+        )?;
+
+        #[cfg(unix)]
+        {
+            if let Some(niceness) = self.niceness {
+                set_niceness(&mut cmd, niceness);
+            }
+        }
+
+        Ok(cmd)
+    }
+
+    pub fn run(mut self) -> Result<CommandOutput, SimpleError> {
+        let started_at = Instant::now();
+        let result = self.run_command();
+
+        if self.log_json {
+            self.log_result(&result, started_at.elapsed());
+        }
+
+        result
+    }
+
+    fn log_result(&self, result: &Result<CommandOutput, SimpleError>, duration: Duration) {
+        let last_chars = |value: &str| {
+            let value = self.redact(value);
+            let chars = value.chars().count();
+            value
+                .chars()
+                .skip(chars.saturating_sub(JSON_LOG_OUTPUT_MAX_CHARS))
+                .collect::<String>()
+        };
+
+        let (exit_code, output, error) = match result {
+            Ok(output) => (output.status.code(), Some(output), None),
+            Err(err) => match err.kind {
+                SimpleErrorKind::Command(status) => (status.code(), None, err.message.as_deref()),
+                _ => (None, None, err.message.as_deref()),
+            },
+        };
+
+        let log = CommandLog {
+            binary: self.binary.to_string_lossy().to_string(),
+            args: self.args.iter().map(|x| self.redact(x)).collect(),
+            exit_code,
+            duration_seconds: duration.as_secs_f64(),
+            stdout: output.map(|x| last_chars(x.stdout.as_str())),
+            stderr: output.map(|x| last_chars(x.stderr.as_str())),
+            error,
+        };
+
+        match serde_json::to_string(&log) {
+            Ok(log) => info!("{}", log),
+            Err(err) => error!("unable to serialize the command log: {}", err),
+        }
+    }
+
+    /// whether the command is only logged: dry run set on this command, or dry run mode of
+    /// `set_dry_run`, unless a runner is set on the thread (see `set_command_runner`)
+    fn skips_execution(&self, dry_run_mode: bool) -> bool {
+        self.dry_run || (dry_run_mode && thread_command_runner().is_none())
+    }
+
+    fn run_command(&mut self) -> Result<CommandOutput, SimpleError> {
+        let args = self.resolved_args()?;
+        let command_string = self.redact(
+            command_with_envs_to_string(
+                self.binary.as_path(),
+                &args.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+                &self.envs,
+            )
+            .as_str(),
+        );
+        let cmd = self.command(&args)?;
+        log_command(command_string.as_str(), &cmd);
+
+        if self.skips_execution(is_dry_run()) {
+            info!("dry run, not executed: {}", command_string);
+            return Ok(dry_run_output(&cmd));
+        }
+
+        let deadline = self.effective_deadline();
+        let mut output = if self.phases.is_empty()
+            && self.map_line.is_none()
+            && !self.log_lines
+            && deadline.is_none()
+            && self.stdin.is_none()
+            && self.sinks.is_empty()
+            && self.progress.is_none()
+        {
+            match run_and_capture(cmd) {
+                Ok(output) => output,
+                Err(err) => return Err(self.redact_error(err)),
+            }
+        } else {
+            let mut phase_tracker = self.phase_tracker()?;
+            let mut map_line = self.map_line.take();
+            let log_lines = self.log_lines;
+            let mut sinks = std::mem::take(&mut self.sinks);
+            let secrets = self.secrets.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+
+            let stdin = self.stdin.take();
+            let progress = self.progress.take();
+
+            let result = run_and_capture_lines(cmd, deadline, stdin, progress, |line| {
+                let line = match map_line.as_mut() {
+                    Some(map_line) => map_line(line)?,
+                    None => line,
+                };
+
+                if log_lines {
+                    let text = redact_secrets(line.text.as_str(), &secrets);
+                    match line.stream {
+                        Stream::Stdout => info!("{}", text),
+                        Stream::Stderr => error!("{}", text),
+                    }
+                }
+
+                if !sinks.is_empty() {
+                    let redacted_line = OutputLine {
+                        stream: line.stream,
+                        text: redact_secrets(line.text.as_str(), &secrets),
+                        at: line.at,
+                    };
+                    sinks.iter_mut().for_each(|x| x.on_line(&redacted_line));
+                }
+
+                phase_tracker.on_line(&line);
+                Some(line)
+            });
+            sinks.iter_mut().for_each(|x| x.flush());
+
+            let mut output = match result {
+                Ok(output) => output,
+                Err(err) => return Err(self.redact_error(err)),
+            };
+
+            output.phases = phase_tracker.finish();
+            output
+        };
+
+        if let Some(inherited) = self.recorded_envs.as_ref() {
+            output.envs = self.envs_snapshot(inherited);
+        }
+
+        if self.fail_on_stderr && !output.stderr.trim().is_empty() {
+            let message = format!(
+                "command {} wrote on stderr: {}",
+                command_string,
+                output.stderr.trim()
+            );
+            return Err(self.redact_error(
+                SimpleError::new(SimpleErrorKind::Other, Some(message)).with_output(output),
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+/// a command of a sequence run by `exec_sequence`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandSpec {
+    pub binary: PathBuf,
+    pub args: Vec<String>,
+    pub envs: Vec<(String, String)>,
+    pub current_dir: Option<PathBuf>,
+    /// the command is killed and the sequence fails if it is not done in time. No timeout by default.
+    pub timeout: Option<Duration>,
+}
+
+impl CommandSpec {
+    pub fn new<P: AsRef<Path>, S: Into<String>>(binary: P, args: Vec<S>) -> Self {
+        CommandSpec {
+            binary: binary.as_ref().to_path_buf(),
+            args: args.into_iter().map(|x| x.into()).collect(),
+            envs: vec![],
+            current_dir: None,
+            timeout: None,
+        }
+    }
+
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(mut self, current_dir: P) -> Self {
+        self.current_dir = Some(current_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn run(&self) -> Result<CommandOutput, SimpleError> {
+        let mut builder = CommandBuilder::new(self.binary.as_path())
+            .args(self.args.clone())
+            .envs(self.envs.clone())
+            .deadline(self.timeout.map(Deadline::after));
+
+        if let Some(current_dir) = self.current_dir.as_ref() {
+            builder = builder.current_dir(current_dir);
+        }
+
+        builder.run()
+    }
+}
+
+/// a command of a sequence failed, with the outputs of the commands which ran before it
+#[derive(Debug)]
+pub struct SequenceError {
+    pub outputs: Vec<CommandOutput>,
+    pub error: SimpleError,
+}
+
+impl From<SequenceError> for SimpleError {
+    fn from(err: SequenceError) -> Self {
+        err.error
+    }
+}
+
+/// run the commands one after the other, stopping at the first failing one
+/// ```no_run
+/// use qovery_engine::cmd::command::{exec_sequence, CommandSpec};
+///
+/// let outputs = exec_sequence(&[
+///     CommandSpec::new("terraform", vec!["init"]).current_dir("/tmp/my-module"),
+///     CommandSpec::new("terraform", vec!["plan", "-out", "tf_plan"]).current_dir("/tmp/my-module"),
+/// ]);
+/// ```
+pub fn exec_sequence(specs: &[CommandSpec]) -> Result<Vec<CommandOutput>, SequenceError> {
+    let mut outputs = Vec::with_capacity(specs.len());
+
+    for spec in specs {
+        match spec.run() {
+            Ok(output) => outputs.push(output),
+            Err(error) => return Err(SequenceError { outputs, error }),
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// replace every `${VAR}` of the value by the value returned by `lookup`
+fn expand_env_vars<F>(value: &str, lookup: F) -> Result<String, SimpleError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!("unterminated variable in argument {}", value)),
+                ))
+            }
+        };
+
+        let name = &rest[start + 2..end];
+        match lookup(name) {
+            Some(var) => expanded.push_str(var.as_str()),
+            None => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!("unknown variable {} in argument {}", name, value)),
+                ))
+            }
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(unix)]
+fn set_niceness(cmd: &mut Command, niceness: i32) {
+    use std::os::unix::process::CommandExt;
+
+    // executed in the child process, right before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, niceness) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::command::{exec_sequence, expand_env_vars, CommandBuilder, CommandSpec};
+    use crate::cmd::deadline::Deadline;
+    use crate::cmd::output::{ChannelSink, FileSink, LogSink, OutputLine, Stream};
+    use crate::cmd::runner::{set_command_runner, MockRunner};
+    use crate::error::SimpleErrorKind;
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_expand_env_vars() {
+        let lookup = |name: &str| match name {
+            "NAMESPACE" => Some("my-namespace".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            expand_env_vars("--namespace=${NAMESPACE}", lookup).unwrap(),
+            "--namespace=my-namespace"
+        );
+        assert_eq!(
+            expand_env_vars("${NAMESPACE}/${EMPTY}x", lookup).unwrap(),
+            "my-namespace/x"
+        );
+        assert_eq!(expand_env_vars("$NAMESPACE", lookup).unwrap(), "$NAMESPACE");
+        assert!(expand_env_vars("${UNKNOWN}", lookup).is_err());
+        assert!(expand_env_vars("${NAMESPACE", lookup).is_err());
+    }
+
+    #[test]
+    fn test_stdin_and_stream_callbacks() {
+        let (stdout_tx, stdout_rx) = mpsc::channel();
+        let (stderr_tx, stderr_rx) = mpsc::channel();
+
+        let output = CommandBuilder::new("sh")
+            .args(vec!["-c", "cat; echo warning >&2"])
+            .stdin("apiVersion: v1\nkind: Namespace\n")
+            .on_stdout(move |line| stdout_tx.send(line.to_string()).unwrap())
+            .on_stderr(move |line| stderr_tx.send(line.to_string()).unwrap())
+            .run()
+            .unwrap();
+
+        assert_eq!(output.stdout, "apiVersion: v1\nkind: Namespace\n");
+        assert_eq!(
+            stdout_rx.try_iter().collect::<Vec<_>>(),
+            vec!["apiVersion: v1", "kind: Namespace"]
+        );
+        assert_eq!(stderr_rx.try_iter().collect::<Vec<_>>(), vec!["warning"]);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let output = CommandBuilder::new("qovery-nonexistent-binary")
+            .args(vec!["delete", "--token", "abcd"])
+            .secret("abcd")
+            .dry_run(true)
+            .run()
+            .unwrap();
+
+        assert_eq!(output.stdout, "");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_dry_run_mode_with_command_runner() {
+        let builder = CommandBuilder::new("helm").args(vec!["list"]);
+        assert!(builder.skips_execution(true));
+        assert!(!builder.skips_execution(false));
+
+        // the runner of the thread takes precedence on the dry run mode
+        set_command_runner(Some(Arc::new(MockRunner::new())));
+        assert!(!builder.skips_execution(true));
+        assert!(CommandBuilder::new("helm")
+            .dry_run(true)
+            .skips_execution(true));
+        set_command_runner(None);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let started_at = Instant::now();
+        let err = CommandBuilder::new("sleep")
+            .arg("10")
+            .timeout(Duration::from_millis(200))
+            .deadline(Some(Deadline::after(Duration::from_secs(60))))
+            .run()
+            .unwrap_err();
+
+        assert!(matches!(err.kind, SimpleErrorKind::Timeout(_)));
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_phases() {
+        let output = CommandBuilder::new("sh")
+            .args(vec![
+                "-c",
+                "echo Initializing the backend...; echo plan; echo Apply complete! Resources: 1 added.",
+            ])
+            .phase("^Initializing", "init")
+            .phase("^Apply complete!", "applied")
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            output
+                .phases
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["init", "applied"]
+        );
+        assert_eq!(
+            output.stdout,
+            "Initializing the backend...\nplan\nApply complete! Resources: 1 added.\n"
+        );
+    }
+
+    #[test]
+    fn test_map_line() {
+        let output = CommandBuilder::new("sh")
+            .args(vec!["-c", "echo token=abcd; echo debug; echo done"])
+            .map_line(|line| match line.text.as_str() {
+                "debug" => None,
+                text => Some(OutputLine {
+                    text: text.replace("abcd", "***"),
+                    ..line
+                }),
+            })
+            .run()
+            .unwrap();
+
+        assert_eq!(output.stdout, "token=***\ndone\n");
+    }
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Deadline::after(Duration::from_secs(2));
+        let output = CommandBuilder::new("sh")
+            .args(vec!["-c", "echo done"])
+            .deadline(Some(deadline))
+            .run()
+            .unwrap();
+        assert_eq!(output.stdout, "done\n");
+
+        // the remaining time is shared, the second command is killed when the deadline is reached
+        let started_at = Instant::now();
+        let err = CommandBuilder::new("sleep")
+            .arg("30")
+            .deadline(Some(deadline))
+            .run()
+            .unwrap_err();
+        assert!(
+            matches!(err.kind, SimpleErrorKind::Timeout(timeout) if timeout == Duration::from_secs(2))
+        );
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+
+        // an exceeded deadline does not even start the command
+        assert!(deadline.is_exceeded());
+        assert!(CommandBuilder::new("true")
+            .deadline(Some(deadline))
+            .run()
+            .is_err());
+    }
+
+    #[test]
+    fn test_secret() {
+        let err = CommandBuilder::new("sh")
+            .args(vec!["-c", "echo denied for my-token >&2; exit 1"])
+            .secret("my-token")
+            .run()
+            .unwrap_err();
+
+        assert_eq!(
+            err.message.unwrap(),
+            "error while executing an internal command: denied for ****"
+        );
+    }
+
+    #[test]
+    fn test_fail_on_stderr() {
+        let err = CommandBuilder::new("sh")
+            .args(vec!["-c", "echo planned; echo deprecated my-token >&2"])
+            .secret("my-token")
+            .fail_on_stderr(true)
+            .run()
+            .unwrap_err();
+
+        assert!(err
+            .message
+            .as_ref()
+            .unwrap()
+            .ends_with("wrote on stderr: deprecated ****"));
+        assert_eq!(err.exit_code(), Some(0));
+        let output = err.output.unwrap();
+        assert_eq!(output.stdout, "planned\n");
+        assert_eq!(output.stderr, "deprecated ****\n");
+    }
+
+    #[test]
+    fn test_sinks() {
+        let path = std::env::temp_dir().join(format!("command-sink-{}.log", std::process::id()));
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        CommandBuilder::new("sh")
+            .args(vec!["-c", "echo token my-token; echo done"])
+            .secret("my-token")
+            .sink(LogSink)
+            .sink(FileSink::open(&path).unwrap())
+            .sink(ChannelSink::new(sender))
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            receiver
+                .try_iter()
+                .map(|x| (x.stream, x.text))
+                .collect::<Vec<_>>(),
+            vec![
+                (Stream::Stdout, "token ****".to_string()),
+                (Stream::Stdout, "done".to_string()),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "token ****\ndone\n"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_envs() {
+        std::env::set_var("QOVERY_TEST_RECORDED_ENV", "inherited");
+
+        let output = CommandBuilder::new("true")
+            .env("DIGITAL_OCEAN_TOKEN", "my-token")
+            .secret("my-token")
+            .record_envs(vec!["QOVERY_TEST_RECORDED_ENV", "QOVERY_TEST_UNSET_ENV"])
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            output.envs,
+            vec![
+                (
+                    "QOVERY_TEST_RECORDED_ENV".to_string(),
+                    "inherited".to_string()
+                ),
+                ("DIGITAL_OCEAN_TOKEN".to_string(), "***".to_string()),
+            ]
+        );
+
+        // a sensitive key is masked even if its value is not declared as a secret
+        let output = CommandBuilder::new("true")
+            .env("AWS_SECRET_ACCESS_KEY", "abcd")
+            .env("TF_LOG", "INFO")
+            .record_envs(vec![])
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            output.envs,
+            vec![
+                ("AWS_SECRET_ACCESS_KEY".to_string(), "***".to_string()),
+                ("TF_LOG".to_string(), "INFO".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_task() {
+        let output = CommandBuilder::new("sh")
+            .args(vec!["-c", "echo $ENGINE_TASK"])
+            .task("deploy-app-123")
+            .run()
+            .unwrap();
+
+        assert_eq!(output.stdout.trim(), "deploy-app-123");
+    }
+
+    #[test]
+    fn test_exec_sequence() {
+        let err = exec_sequence(&[
+            CommandSpec::new("sh", vec!["-c", "echo first"]),
+            CommandSpec::new("sh", vec!["-c", "exit 2"]),
+            CommandSpec::new("sh", vec!["-c", "echo never run"]),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.outputs.len(), 1);
+        assert_eq!(err.outputs[0].stdout, "first\n");
+        assert!(
+            matches!(err.error.kind, SimpleErrorKind::Command(status) if status.code() == Some(2))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use crate::error::{SimpleError, SimpleErrorKind};
+
+/// A point in time a whole operation must be done by.
+///
+/// The same deadline can be given to each command of a sequence, each one then gets the time left
+/// instead of its own timeout, so the whole sequence is bounded
+/// (ex: "the whole deployment must be done in 20 minutes").
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline {
+    started_at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// a deadline `timeout` from now
+    pub fn after(timeout: Duration) -> Self {
+        Deadline {
+            started_at: Instant::now(),
+            timeout,
+        }
+    }
+
+    /// total time given to the operation
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.started_at + self.timeout
+    }
+
+    /// time left before the deadline, zero once it is exceeded
+    pub fn remaining(&self) -> Duration {
+        self.timeout
+            .checked_sub(self.started_at.elapsed())
+            .unwrap_or_default()
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining() == Duration::from_secs(0)
+    }
+
+    /// time left before the deadline, or a `SimpleErrorKind::Timeout` error once it is exceeded
+    pub fn check(&self) -> Result<Duration, SimpleError> {
+        match self.remaining() {
+            remaining if remaining > Duration::from_secs(0) => Ok(remaining),
+            _ => Err(self.timeout_error()),
+        }
+    }
+
+    pub(crate) fn timeout_error(&self) -> SimpleError {
+        SimpleError::new(
+            SimpleErrorKind::Timeout(self.timeout),
+            Some("the operation deadline is exceeded"),
+        )
+    }
+}
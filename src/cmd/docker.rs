@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use crate::cmd::command::CommandBuilder;
+use crate::cmd::deadline::Deadline;
+use crate::error::{SimpleError, SimpleErrorKind};
+
+/// a healthy daemon answers `docker info` in well under a second
+const DOCKER_INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// check that the Docker daemon answers, so registry operations (login, tag, push) fail upfront
+/// with an actionable error instead of an obscure push failure
+pub fn docker_available() -> Result<(), SimpleError> {
+    docker_available_with_envs(vec![])
+}
+
+/// same as `docker_available`, with envs to reach the daemon (ex: `DOCKER_HOST`)
+pub fn docker_available_with_envs(envs: Vec<(&str, &str)>) -> Result<(), SimpleError> {
+    let result = CommandBuilder::new("docker")
+        .arg("info")
+        .envs(envs)
+        .deadline(Some(Deadline::after(DOCKER_INFO_TIMEOUT)))
+        .run();
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => match err.kind {
+            SimpleErrorKind::Timeout(timeout) => Err(SimpleError::new(
+                SimpleErrorKind::Timeout(timeout),
+                Some(format!(
+                    "the Docker daemon did not answer within {} seconds, it may be overloaded or stuck",
+                    timeout.as_secs()
+                )),
+            )),
+            _ if is_daemon_unreachable_error(err.message.as_deref().unwrap_or_default()) => {
+                Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "the Docker daemon is not reachable, check that it is running \
+                        (and that DOCKER_HOST is right if it is set): {}",
+                        err.message.unwrap_or_default()
+                    )),
+                ))
+            }
+            _ => Err(err),
+        },
+    }
+}
+
+/// ex: "Cannot connect to the Docker daemon at unix:///var/run/docker.sock. Is the docker daemon running?"
+fn is_daemon_unreachable_error(stderr: &str) -> bool {
+    stderr.contains("Cannot connect to the Docker daemon")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::docker::docker_available;
+    use crate::cmd::runner::{set_command_runner, MockRunner};
+    use crate::error::SimpleErrorKind;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_docker_available() {
+        let runner = MockRunner::new().on(
+            "docker",
+            &["info"],
+            MockRunner::output(
+                "",
+                "Cannot connect to the Docker daemon at unix:///var/run/docker.sock. Is the docker daemon running?",
+                1,
+            ),
+        );
+        set_command_runner(Some(Arc::new(runner)));
+
+        let err = docker_available().unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Other));
+        assert!(err
+            .message
+            .unwrap()
+            .starts_with("the Docker daemon is not reachable"));
+
+        set_command_runner(Some(Arc::new(MockRunner::new().on(
+            "docker",
+            &["info"],
+            MockRunner::output("Server Version: 19.03.13", "", 0),
+        ))));
+        assert!(docker_available().is_ok());
+
+        set_command_runner(None);
+    }
+}
@@ -389,7 +389,7 @@ where
                 }
                 None => Err(err),
             },
-            SimpleErrorKind::Other => Err(err),
+            _ => Err(err),
         },
         _ => Ok(()),
     }
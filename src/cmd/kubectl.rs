@@ -1,16 +1,40 @@
 use std::io::Error;
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use retry::delay::Fibonacci;
 use retry::OperationResult;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::cmd::structs::{
     Item, KubernetesJob, KubernetesList, KubernetesNode, KubernetesPod, KubernetesPodStatusPhase,
-    KubernetesService,
+    KubernetesService, KubernetesVersion,
 };
-use crate::cmd::utilities::exec_with_envs_and_output;
+use crate::cmd::utilities::{exec_capture, exec_with_envs_and_output, CommandOutput};
 use crate::error::{SimpleError, SimpleErrorKind};
 use crate::constants::KUBECONFIG;
+use crate::metrics::{metrics, RETRY_COUNT};
+use crate::string::redact_secrets;
+
+const READY_NODES_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const NAMESPACE_DELETION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// kubectl is supported within one minor version (older or newer) of the API server
+const KUBECTL_SUPPORTED_MINOR_SKEW: u32 = 1;
+/// maximum number of characters of the kubectl output in the errors of `kubectl_json`
+const KUBECTL_JSON_ERROR_OUTPUT_MAX_CHARS: usize = 200;
+
+/// versions of kubectl and of the API server of a cluster
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkewReport {
+    pub client_version: String,
+    pub server_version: String,
+    /// number of minor versions between kubectl and the API server
+    pub minor_skew: u32,
+    /// false if kubectl is too far from the API server, it may then behave unexpectedly
+    pub within_supported_skew: bool,
+}
 
 pub fn kubectl_exec_with_output<F, X>(
     args: Vec<&str>,
@@ -30,6 +54,88 @@ where
     Ok(())
 }
 
+fn kubectl_args<'a>(kubeconfig: &'a str, namespace: Option<&'a str>, args: &[&'a str]) -> Vec<&'a str> {
+    let mut kubectl_args = vec!["--kubeconfig", kubeconfig];
+    if let Some(namespace) = namespace {
+        kubectl_args.extend(&["--namespace", namespace]);
+    }
+    kubectl_args.extend(args);
+    kubectl_args
+}
+
+/// run kubectl against the given kubeconfig (and namespace, if any) and capture its output
+pub fn kubectl(
+    kubeconfig: &Path,
+    namespace: Option<&str>,
+    args: &[&str],
+) -> Result<CommandOutput, SimpleError> {
+    let kubeconfig = kubeconfig.to_string_lossy();
+    exec_capture(
+        "kubectl",
+        kubectl_args(kubeconfig.as_ref(), namespace, args),
+        Vec::<(&str, &str)>::new(),
+    )
+}
+
+/// run kubectl with `-o json` and deserialize its output
+pub fn kubectl_json<T>(
+    kubeconfig: &Path,
+    namespace: Option<&str>,
+    args: &[&str],
+) -> Result<T, SimpleError>
+where
+    T: DeserializeOwned,
+{
+    let mut json_args = args.to_vec();
+    json_args.extend(&["-o", "json"]);
+
+    let output = kubectl(kubeconfig, namespace, &json_args)?;
+
+    match serde_json::from_str::<T>(output.stdout.as_str()) {
+        Ok(x) => Ok(x),
+        Err(err) => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "unable to deserialize the output of kubectl {}: {} (output: {})",
+                args.join(" "),
+                err,
+                output_excerpt(output.stdout.as_str())
+            )),
+        )),
+    }
+}
+
+/// beginning of the kubectl output, without the values of the `data` and `stringData` fields
+/// (ex: of a `kubectl get secret -o json`)
+fn output_excerpt(stdout: &str) -> String {
+    let mut secrets = vec![];
+    if let Ok(json) = serde_json::from_str::<Value>(stdout) {
+        collect_data_values(&json, &mut secrets);
+    }
+    let secrets = secrets.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+
+    redact_secrets(stdout, &secrets)
+        .chars()
+        .take(KUBECTL_JSON_ERROR_OUTPUT_MAX_CHARS)
+        .collect()
+}
+
+fn collect_data_values(value: &Value, values: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                match (key.as_str(), field) {
+                    ("data", Value::Object(data)) | ("stringData", Value::Object(data)) => values
+                        .extend(data.values().filter_map(|x| x.as_str()).map(|x| x.to_string())),
+                    _ => collect_data_values(field, values),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|x| collect_data_values(x, values)),
+        _ => {}
+    }
+}
+
 /*#[derive(DeserializeQuery)]
 struct PodDescribe {
     #[query(".status.containerStatuses[0]..restartCount")]
@@ -171,6 +277,7 @@ where
                 _ => {
                     let t = format!("pod with selector: {} is not ready yet", selector);
                     info!("{}", t.as_str());
+                    metrics().increment(RETRY_COUNT, 1, &[("operation", "kubectl_pod_ready")]);
                     OperationResult::Retry(t)
                 }
             },
@@ -279,6 +386,7 @@ where
                 _ => {
                     let t = format!("job {} is not ready yet", job_name);
                     info!("{}", t.as_str());
+                    metrics().increment(RETRY_COUNT, 1, &[("operation", "kubectl_job_ready")]);
                     OperationResult::Retry(t)
                 }
             },
@@ -669,3 +777,266 @@ where
 
     Ok(result)
 }
+
+/// poll the nodes of the cluster until at least `expected` of them are `Ready`,
+/// or fail once `timeout` is elapsed
+pub fn wait_for_ready_nodes(
+    kubeconfig: &Path,
+    expected: usize,
+    timeout: Duration,
+) -> Result<(), SimpleError> {
+    let started_at = Instant::now();
+
+    loop {
+        let nodes: KubernetesList<KubernetesNode> =
+            kubectl_json(kubeconfig, None, &["get", "nodes"])?;
+        let ready_nodes = nodes.items.iter().filter(|node| node.is_ready()).count();
+        if ready_nodes >= expected {
+            return Ok(());
+        }
+
+        info!(
+            "{}/{} nodes are ready, waiting for the other ones",
+            ready_nodes, expected
+        );
+
+        let elapsed = started_at.elapsed();
+        if elapsed >= timeout {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Timeout(timeout),
+                Some(format!(
+                    "only {} nodes are ready out of the {} expected",
+                    ready_nodes, expected
+                )),
+            ));
+        }
+
+        thread::sleep(READY_NODES_POLL_INTERVAL.min(timeout - elapsed));
+    }
+}
+
+/// poll the namespace until it does not exist anymore, or fail once `timeout` is elapsed
+/// (ex: before re-creating a namespace which may still be `Terminating`)
+pub fn wait_for_namespace_deleted(
+    kubeconfig: &Path,
+    namespace: &str,
+    timeout: Duration,
+) -> Result<(), SimpleError> {
+    let started_at = Instant::now();
+
+    loop {
+        match kubectl(kubeconfig, None, &["get", "namespace", namespace]) {
+            Err(err) if is_not_found_error(err.message.as_deref().unwrap_or_default()) => {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+            Ok(_) => {}
+        }
+
+        info!("namespace {} is still being deleted", namespace);
+
+        let elapsed = started_at.elapsed();
+        if elapsed >= timeout {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Timeout(timeout),
+                Some(format!(
+                    "namespace {} is still not deleted, a finalizer may be stuck \
+                    (check its spec.finalizers and the resources left in it)",
+                    namespace
+                )),
+            ));
+        }
+
+        thread::sleep(NAMESPACE_DELETION_POLL_INTERVAL.min(timeout - elapsed));
+    }
+}
+
+/// kubectl reports 404 as "Error from server (NotFound)"
+fn is_not_found_error(stderr: &str) -> bool {
+    stderr.contains("(NotFound)")
+}
+
+/// make an authenticated call to the API server, failing with `SimpleErrorKind::Unauthorized` if the
+/// kubeconfig credentials are rejected (expired token or certificate), to tell it apart from an
+/// unreachable cluster
+pub fn validate_kubeconfig_auth(kubeconfig: &Path) -> Result<(), SimpleError> {
+    match kubectl(kubeconfig, None, &["auth", "can-i", "--list"]) {
+        Ok(_) => Ok(()),
+        Err(err) if is_unauthorized_error(err.message.as_deref().unwrap_or_default()) => {
+            Err(SimpleError::new(
+                SimpleErrorKind::Unauthorized,
+                Some(format!(
+                    "the credentials of the kubeconfig {} are rejected: {}",
+                    kubeconfig.display(),
+                    err.message.unwrap_or_default()
+                )),
+            ))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// kubectl reports 401 as "You must be logged in to the server (Unauthorized)"
+/// and 403 as "Error from server (Forbidden)"
+fn is_unauthorized_error(stderr: &str) -> bool {
+    stderr.contains("(Unauthorized)") || stderr.contains("(Forbidden)")
+}
+
+/// compare the kubectl and API server versions. A skew out of the supported range is logged
+/// and reported, it is not an error.
+pub fn check_kubectl_skew(kubeconfig: &Path) -> Result<SkewReport, SimpleError> {
+    let version = kubectl_json::<KubernetesVersion>(kubeconfig, None, &["version"])?;
+    let report = skew_report(&version)?;
+
+    if !report.within_supported_skew {
+        warn!(
+            "kubectl {} is {} minor versions away from the Kubernetes API server {}, only {} is supported",
+            report.client_version,
+            report.minor_skew,
+            report.server_version,
+            KUBECTL_SUPPORTED_MINOR_SKEW
+        );
+    }
+
+    Ok(report)
+}
+
+fn skew_report(version: &KubernetesVersion) -> Result<SkewReport, SimpleError> {
+    let server_version = match version.server_version.as_ref() {
+        Some(server_version) => server_version,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some("kubectl did not return the version of the Kubernetes API server"),
+            ))
+        }
+    };
+
+    let version_number = |number: &str| -> Result<u32, SimpleError> {
+        let digits = number.trim_end_matches(|c: char| !c.is_ascii_digit());
+        match digits.parse::<u32>() {
+            Ok(number) => Ok(number),
+            Err(_) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("unable to parse the Kubernetes version number {}", number)),
+            )),
+        }
+    };
+
+    let client_major = version_number(version.client_version.major.as_str())?;
+    let client_minor = version_number(version.client_version.minor.as_str())?;
+    let server_major = version_number(server_version.major.as_str())?;
+    let server_minor = version_number(server_version.minor.as_str())?;
+
+    let minor_skew = match client_minor > server_minor {
+        true => client_minor - server_minor,
+        false => server_minor - client_minor,
+    };
+
+    Ok(SkewReport {
+        client_version: version.client_version.git_version.clone(),
+        server_version: server_version.git_version.clone(),
+        minor_skew,
+        within_supported_skew: client_major == server_major
+            && minor_skew <= KUBECTL_SUPPORTED_MINOR_SKEW,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::kubectl::{
+        is_unauthorized_error, kubectl_args, output_excerpt, skew_report,
+        wait_for_namespace_deleted,
+    };
+    use crate::cmd::runner::{set_command_runner, MockRunner};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::cmd::structs::KubernetesVersion;
+
+    #[test]
+    fn test_output_excerpt() {
+        let secret = r#"{"kind": "Secret", "data": {"password": "c2VjcmV0"}, "type": "Opaque"}"#;
+        assert_eq!(
+            output_excerpt(secret),
+            r#"{"kind": "Secret", "data": {"password": "****"}, "type": "Opaque"}"#
+        );
+
+        let list = r#"{"items": [{"stringData": {"token": "abcd"}}]}"#;
+        assert!(!output_excerpt(list).contains("abcd"));
+
+        assert_eq!(output_excerpt("é".repeat(500).as_str()).chars().count(), 200);
+    }
+
+    #[test]
+    fn test_kubectl_args() {
+        assert_eq!(
+            kubectl_args("/tmp/kubeconfig", None, &["get", "pods"]),
+            vec!["--kubeconfig", "/tmp/kubeconfig", "get", "pods"]
+        );
+        assert_eq!(
+            kubectl_args("/tmp/kubeconfig", Some("my-namespace"), &["get", "pods"]),
+            vec![
+                "--kubeconfig",
+                "/tmp/kubeconfig",
+                "--namespace",
+                "my-namespace",
+                "get",
+                "pods"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skew_report() {
+        let version = serde_json::from_str::<KubernetesVersion>(
+            r#"{
+  "clientVersion": {"major": "1", "minor": "16", "gitVersion": "v1.16.3", "platform": "linux/amd64"},
+  "serverVersion": {"major": "1", "minor": "18+", "gitVersion": "v1.18.8-do.0", "platform": "linux/amd64"}
+}"#,
+        )
+        .unwrap();
+
+        let report = skew_report(&version).unwrap();
+        assert_eq!(report.client_version, "v1.16.3");
+        assert_eq!(report.server_version, "v1.18.8-do.0");
+        assert_eq!(report.minor_skew, 2);
+        assert!(!report.within_supported_skew);
+    }
+
+    #[test]
+    fn test_is_unauthorized_error() {
+        assert!(is_unauthorized_error(
+            "error: You must be logged in to the server (Unauthorized)"
+        ));
+        assert!(is_unauthorized_error(
+            "Error from server (Forbidden): selfsubjectrulesreviews.authorization.k8s.io is forbidden"
+        ));
+        assert!(!is_unauthorized_error(
+            "Unable to connect to the server: dial tcp: lookup 9a1b2c3d.k8s.ondigitalocean.com: no such host"
+        ));
+    }
+
+    #[test]
+    fn test_wait_for_namespace_deleted() {
+        let runner = MockRunner::new().on(
+            "kubectl",
+            &["--kubeconfig", "/tmp/kubeconfig", "get", "namespace", "z1234"],
+            MockRunner::output(
+                "",
+                "Error from server (NotFound): namespaces \"z1234\" not found",
+                1,
+            ),
+        );
+        set_command_runner(Some(Arc::new(runner)));
+
+        let result = wait_for_namespace_deleted(
+            Path::new("/tmp/kubeconfig"),
+            "z1234",
+            Duration::from_secs(1),
+        );
+        set_command_runner(None);
+
+        assert!(result.is_ok());
+    }
+}
@@ -1,5 +1,12 @@
+pub mod async_utilities;
+pub mod child;
+pub mod command;
+pub mod deadline;
+pub mod docker;
 pub mod helm;
 pub mod kubectl;
+pub mod output;
+pub mod runner;
 pub mod structs;
 pub mod terraform;
 pub mod utilities;
@@ -0,0 +1,525 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::mpsc::{sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::error::SimpleError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// a line written by a command on stdout or stderr, without its line terminator
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLine {
+    pub stream: Stream,
+    pub text: String,
+    /// when the line has been read from the command
+    pub at: Instant,
+}
+
+impl OutputLine {
+    /// a line read now
+    pub fn new(stream: Stream, text: String) -> Self {
+        OutputLine {
+            stream,
+            text,
+            at: Instant::now(),
+        }
+    }
+}
+
+/// A destination of the output lines of a command, see `CommandBuilder::sink`.
+/// Every sink of a command receives every line, from the thread running the command.
+pub trait OutputSink {
+    fn on_line(&mut self, line: &OutputLine);
+
+    /// called once the command is done, whatever its result
+    fn flush(&mut self) {}
+}
+
+/// logs stdout lines as info and stderr lines as error
+pub struct LogSink;
+
+impl OutputSink for LogSink {
+    fn on_line(&mut self, line: &OutputLine) {
+        match line.stream {
+            Stream::Stdout => info!("{}", line.text),
+            Stream::Stderr => error!("{}", line.text),
+        }
+    }
+}
+
+/// appends the lines, stdout and stderr interleaved, to a file
+pub struct FileSink {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl FileSink {
+    /// open `path` for appending, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SimpleError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+
+        Ok(FileSink {
+            path: path.as_ref().to_path_buf(),
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+
+    fn write_error(&mut self, err: std::io::Error) {
+        // the file is not written anymore, but the command goes on
+        error!(
+            "unable to write the command output to {}: {}",
+            self.path.display(),
+            err
+        );
+        self.writer = None;
+    }
+}
+
+impl OutputSink for FileSink {
+    fn on_line(&mut self, line: &OutputLine) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writeln!(writer, "{}", line.text) {
+                self.write_error(err);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writer.flush() {
+                self.write_error(err);
+            }
+        }
+    }
+}
+
+/// sends the lines to a channel (ex: to stream them to a UI). Lines are dropped once the receiver is gone.
+pub struct ChannelSink {
+    sender: Sender<OutputLine>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: Sender<OutputLine>) -> Self {
+        ChannelSink { sender }
+    }
+}
+
+impl OutputSink for ChannelSink {
+    fn on_line(&mut self, line: &OutputLine) {
+        let _ = self.sender.send(line.clone());
+    }
+}
+
+/// what to do when the consumer of a command output is slower than the command producing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backpressure {
+    /// stop reading the command output until the consumer catches up. Nothing is lost,
+    /// but the command itself blocks as soon as its pipe buffer is full.
+    Block,
+    /// keep reading the command output and drop the oldest buffered lines.
+    /// The command is never slowed down, but the consumer misses lines.
+    DropOldest,
+}
+
+/// time spent in a phase of a command, from the line matching its marker to the next phase or the command end
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseDuration {
+    pub name: String,
+    pub duration: Duration,
+}
+
+/// detect the phases of a command from its output lines (ex: terraform "Initializing..." or "Apply complete!")
+pub(crate) struct PhaseTracker {
+    markers: Vec<(Regex, String)>,
+    current_phase: Option<(String, Instant)>,
+    phases: Vec<PhaseDuration>,
+}
+
+impl PhaseTracker {
+    pub fn new(markers: Vec<(Regex, String)>) -> Self {
+        PhaseTracker {
+            markers,
+            current_phase: None,
+            phases: vec![],
+        }
+    }
+
+    /// start a new phase if the line matches one of the markers
+    pub fn on_line(&mut self, line: &OutputLine) {
+        let phase_name = match self
+            .markers
+            .iter()
+            .find(|(marker, _)| marker.is_match(&line.text))
+        {
+            Some((_, phase_name)) => phase_name.clone(),
+            None => return,
+        };
+
+        self.end_current_phase();
+        info!("phase: {}", phase_name);
+        self.current_phase = Some((phase_name, Instant::now()));
+    }
+
+    fn end_current_phase(&mut self) {
+        if let Some((name, started_at)) = self.current_phase.take() {
+            self.phases.push(PhaseDuration {
+                name,
+                duration: started_at.elapsed(),
+            });
+        }
+    }
+
+    /// end the current phase and return the duration of every phase seen
+    pub fn finish(mut self) -> Vec<PhaseDuration> {
+        self.end_current_phase();
+        self.phases
+    }
+}
+
+/// output read so far from a running command, see `ProgressReporter`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct CommandProgress {
+    pub stdout_lines: u64,
+    pub stderr_lines: u64,
+    /// bytes of the lines read, without their line terminator
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// call `on_progress` every `every_lines` output lines and every `every` while the command runs,
+/// even if it writes nothing (ex: "terraform apply" waiting for a load balancer), and once
+/// its output is fully read. `0` lines or a zero duration disables the matching trigger.
+pub struct ProgressReporter<'a> {
+    every_lines: u64,
+    every: Duration,
+    on_progress: Box<dyn FnMut(CommandProgress) + 'a>,
+    progress: CommandProgress,
+    started_at: Instant,
+    reported_at: Instant,
+    lines_since_report: u64,
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub fn new<F>(every_lines: u64, every: Duration, on_progress: F) -> Self
+    where
+        F: FnMut(CommandProgress) + 'a,
+    {
+        let now = Instant::now();
+        ProgressReporter {
+            every_lines,
+            every,
+            on_progress: Box::new(on_progress),
+            progress: CommandProgress::default(),
+            started_at: now,
+            reported_at: now,
+            lines_since_report: 0,
+        }
+    }
+
+    /// count the line, and report if `every_lines` lines or `every` elapsed since the last report
+    pub(crate) fn on_line(&mut self, line: &OutputLine) {
+        let bytes = line.text.len() as u64;
+        match line.stream {
+            Stream::Stdout => {
+                self.progress.stdout_lines += 1;
+                self.progress.stdout_bytes += bytes;
+            }
+            Stream::Stderr => {
+                self.progress.stderr_lines += 1;
+                self.progress.stderr_bytes += bytes;
+            }
+        }
+
+        self.lines_since_report += 1;
+        if self.every_lines > 0 && self.lines_since_report >= self.every_lines {
+            self.report();
+        } else {
+            self.tick();
+        }
+    }
+
+    /// time left before the next periodic report, `None` if there is no periodic report
+    pub(crate) fn next_report_in(&self) -> Option<Duration> {
+        if self.every == Duration::from_secs(0) {
+            return None;
+        }
+
+        Some(
+            self.every
+                .checked_sub(self.reported_at.elapsed())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// report if `every` elapsed since the last report
+    pub(crate) fn tick(&mut self) {
+        if self.next_report_in() == Some(Duration::from_secs(0)) {
+            self.report();
+        }
+    }
+
+    /// last report, once the output is fully read
+    pub(crate) fn finish(&mut self) {
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.progress.elapsed = self.started_at.elapsed();
+        self.reported_at = Instant::now();
+        self.lines_since_report = 0;
+        (self.on_progress)(self.progress);
+    }
+}
+
+/// read every line of the stream on a dedicated thread, and hand it to `on_line`.
+/// A last line without trailing newline (ex: a final prompt or error) is handed too, once the stream ends.
+fn spawn_reader<R, F>(stream: Stream, reader: R, mut on_line: F) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    F: FnMut(OutputLine) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut buffer = vec![];
+
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {
+                    while buffer.last() == Some(&b'\n') || buffer.last() == Some(&b'\r') {
+                        buffer.pop();
+                    }
+
+                    on_line(OutputLine::new(
+                        stream,
+                        String::from_utf8_lossy(&buffer).to_string(),
+                    ))
+                }
+                Err(err) => {
+                    error!("error while reading the command {:?}: {:?}", stream, err);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// read stdout and stderr of the child concurrently, each one on its own thread.
+/// Lines are handed to `on_line` as soon as they are read.
+pub(crate) fn spawn_output_readers<F>(child: &mut Child, on_line: F) -> Vec<JoinHandle<()>>
+where
+    F: FnMut(OutputLine) + Send + Clone + 'static,
+{
+    let mut readers = Vec::with_capacity(2);
+
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(spawn_reader(Stream::Stdout, stdout, on_line.clone()));
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(spawn_reader(Stream::Stderr, stderr, on_line));
+    }
+
+    readers
+}
+
+struct DropOldestQueueState {
+    lines: VecDeque<OutputLine>,
+    closed: bool,
+    dropped: usize,
+}
+
+struct DropOldestQueue {
+    state: Mutex<DropOldestQueueState>,
+    available: Condvar,
+    capacity: usize,
+}
+
+impl DropOldestQueue {
+    fn push(&self, line: OutputLine) {
+        let mut state = self.state.lock().unwrap();
+        if state.lines.len() >= self.capacity {
+            state.lines.pop_front();
+            state.dropped += 1;
+        }
+        state.lines.push_back(line);
+        self.available.notify_one();
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.available.notify_all();
+    }
+
+    /// wait for the next line, or return None once the queue is closed and empty
+    fn pop(&self) -> Option<OutputLine> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.dropped > 0 {
+                warn!(
+                    "{} command output lines have been dropped, they were not consumed fast enough",
+                    state.dropped
+                );
+                state.dropped = 0;
+            }
+            if let Some(line) = state.lines.pop_front() {
+                return Some(line);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+}
+
+/// a bounded channel of output lines following the given backpressure policy.
+/// The sending side must be closed by calling the returned closer once every line is sent.
+pub(crate) fn bounded_output_channel(
+    capacity: usize,
+    backpressure: Backpressure,
+) -> (
+    impl FnMut(OutputLine) + Send + Clone + 'static,
+    impl FnOnce() + Send + 'static,
+    Receiver<OutputLine>,
+) {
+    let capacity = capacity.max(1);
+
+    let (tx, rx): (SyncSender<OutputLine>, Receiver<OutputLine>) = match backpressure {
+        Backpressure::Block => sync_channel(capacity),
+        // lines are buffered in the queue, the forwarder thread holds at most one more line
+        Backpressure::DropOldest => sync_channel(0),
+    };
+
+    let queue = Arc::new(DropOldestQueue {
+        state: Mutex::new(DropOldestQueueState {
+            lines: VecDeque::with_capacity(capacity),
+            closed: false,
+            dropped: 0,
+        }),
+        available: Condvar::new(),
+        capacity,
+    });
+
+    if backpressure == Backpressure::DropOldest {
+        let queue = queue.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            while let Some(line) = queue.pop() {
+                if tx.send(line).is_err() {
+                    // the receiver is gone, there is no one left to forward lines to
+                    break;
+                }
+            }
+        });
+    }
+
+    let push_queue = queue.clone();
+    let send = move |line: OutputLine| match backpressure {
+        // an error means the receiver is gone: drop the line but keep draining the command output
+        Backpressure::Block => {
+            let _ = tx.send(line);
+        }
+        Backpressure::DropOldest => push_queue.push(line),
+    };
+
+    let close = move || queue.close();
+
+    (send, close, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::output::{Backpressure, Stream};
+    use crate::cmd::utilities::{exec_capture, exec_streaming, exec_with_output};
+    use crate::error::SimpleErrorKind;
+
+    #[test]
+    fn test_partial_last_line() {
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        exec_with_output(
+            "sh",
+            vec![
+                "-c",
+                "printf 'first\\nError: no newline'; printf 'warning' >&2",
+            ],
+            |line| stdout.push(line.unwrap()),
+            |line| stderr.push(line.unwrap()),
+        )
+        .unwrap();
+
+        assert_eq!(stdout, vec!["first", "Error: no newline"]);
+        assert_eq!(stderr, vec!["warning"]);
+
+        let output = exec_capture(
+            "sh",
+            vec!["-c", "printf 'summary'"],
+            Vec::<(&str, &str)>::new(),
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "summary");
+    }
+
+    #[test]
+    fn test_exec_streaming() {
+        let (handle, receiver) = exec_streaming(
+            "sh",
+            vec!["-c", "echo out; echo err >&2"],
+            1,
+            Backpressure::Block,
+        );
+
+        let mut lines = receiver.iter().collect::<Vec<_>>();
+        lines.sort_by_key(|x| x.stream == Stream::Stderr);
+
+        assert!(handle.join().is_ok());
+        assert_eq!(
+            lines
+                .into_iter()
+                .map(|x| (x.stream, x.text))
+                .collect::<Vec<_>>(),
+            vec![
+                (Stream::Stdout, "out".to_string()),
+                (Stream::Stderr, "err".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exec_streaming_error_has_stderr() {
+        let (handle, receiver) = exec_streaming(
+            "sh",
+            vec!["-c", "echo planning; echo denied >&2; exit 3"],
+            10,
+            Backpressure::Block,
+        );
+        let _ = receiver.iter().count();
+
+        let err = handle.join().unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(3)));
+        assert!(err.message.as_ref().unwrap().ends_with(": denied"));
+        assert_eq!(err.exit_code(), Some(3));
+        assert_eq!(err.output.unwrap().stderr, "denied");
+    }
+}
@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cmd::utilities::{
+    command_with_envs_to_string, current_dir_of, spawn_error, CommandOutput,
+};
+use crate::error::{SimpleError, SimpleErrorKind};
+
+/// Runs the commands built by the exec functions (`exec`, `exec_capture`, `CommandBuilder::run`...).
+///
+/// By default the commands are spawned, `set_command_runner` replaces that by another runner
+/// (ex: a `MockRunner` so unit tests do not need the real binaries).
+pub trait CommandRunner: Send + Sync {
+    /// run the command to completion and return its output, whatever its exit status
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput, SimpleError>;
+}
+
+/// spawns the command and waits for it
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput, SimpleError> {
+        let current_dir = current_dir_of(cmd);
+        let output = cmd.output().map_err(|err| spawn_error(cmd, err))?;
+
+        Ok(CommandOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            status: output.status,
+            current_dir,
+            phases: vec![],
+            envs: vec![],
+        })
+    }
+}
+
+thread_local! {
+    static COMMAND_RUNNER: RefCell<Option<Arc<dyn CommandRunner>>> = RefCell::new(None);
+}
+
+/// run the commands executed from the current thread with the given runner instead of spawning them.
+/// It is per thread so tests running in parallel can each use their own `MockRunner`.
+/// `None` (the default) spawns the commands.
+pub fn set_command_runner(runner: Option<Arc<dyn CommandRunner>>) {
+    COMMAND_RUNNER.with(|current_runner| *current_runner.borrow_mut() = runner);
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// log the commands instead of running them, from every thread (ex: to rehearse a destructive workflow).
+/// Each command then succeeds with an empty output. A runner set with `set_command_runner` still
+/// takes precedence on its thread. Disabled by default.
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::SeqCst);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// the runner set with `set_command_runner` on the current thread, if any
+pub(crate) fn thread_command_runner() -> Option<Arc<dyn CommandRunner>> {
+    COMMAND_RUNNER.with(|current_runner| current_runner.borrow().clone())
+}
+
+/// the runner set with `set_command_runner` on the current thread, if any,
+/// or the `DryRunRunner` in dry run mode
+pub(crate) fn command_runner() -> Option<Arc<dyn CommandRunner>> {
+    thread_command_runner().or_else(|| match is_dry_run() {
+        true => Some(Arc::new(DryRunRunner)),
+        false => None,
+    })
+}
+
+/// logs the commands without running them, see `set_dry_run`
+pub struct DryRunRunner;
+
+impl CommandRunner for DryRunRunner {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput, SimpleError> {
+        let args = cmd
+            .get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let envs = cmd
+            .get_envs()
+            .filter_map(|(k, v)| v.map(|v| (k.to_string_lossy(), v.to_string_lossy())))
+            .collect::<Vec<_>>();
+
+        // the values of the sensitive envs are masked, as in the log of the command
+        info!(
+            "dry run, not executed: {}",
+            command_with_envs_to_string(
+                cmd.get_program(),
+                &args.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+                &envs,
+            )
+        );
+
+        Ok(dry_run_output(cmd))
+    }
+}
+
+/// empty output of a successful command, returned in dry run mode instead of running `cmd`
+pub(crate) fn dry_run_output(cmd: &Command) -> CommandOutput {
+    CommandOutput {
+        current_dir: current_dir_of(cmd),
+        ..MockRunner::output("", "", 0)
+    }
+}
+
+/// In-memory runner returning pre-programmed outputs, keyed by the binary name and the args.
+/// A command without programmed output fails with `SimpleErrorKind::NotFound`.
+/// ```
+/// use std::sync::Arc;
+/// use qovery_engine::cmd::runner::{set_command_runner, MockRunner};
+/// use qovery_engine::cmd::utilities::exec_capture;
+///
+/// let runner = Arc::new(MockRunner::new().on(
+///     "kubectl",
+///     &["get", "nodes"],
+///     MockRunner::output("node-1   Ready", "", 0),
+/// ));
+/// set_command_runner(Some(runner.clone()));
+///
+/// let output = exec_capture("kubectl", vec!["get", "nodes"], Vec::<(&str, &str)>::new()).unwrap();
+/// assert_eq!(output.stdout, "node-1   Ready");
+/// assert_eq!(runner.calls(), vec!["kubectl get nodes"]);
+///
+/// set_command_runner(None);
+/// ```
+#[derive(Default)]
+pub struct MockRunner {
+    outputs: Vec<(String, Vec<String>, CommandOutput)>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl MockRunner {
+    pub fn new() -> Self {
+        MockRunner::default()
+    }
+
+    /// return `output` when `binary` is executed with exactly `args`
+    pub fn on(mut self, binary: &str, args: &[&str], output: CommandOutput) -> Self {
+        self.outputs.push((
+            binary.to_string(),
+            args.iter().map(|x| x.to_string()).collect(),
+            output,
+        ));
+        self
+    }
+
+    /// output of a command which wrote `stdout` and `stderr` and exited with `exit_code`
+    pub fn output(stdout: &str, stderr: &str, exit_code: i32) -> CommandOutput {
+        CommandOutput {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            status: exit_status(exit_code),
+            current_dir: Default::default(),
+            phases: vec![],
+            envs: vec![],
+        }
+    }
+
+    /// the commands run so far, as "binary arg1 arg2"
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl CommandRunner for MockRunner {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput, SimpleError> {
+        let binary = Path::new(cmd.get_program())
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let args = cmd
+            .get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        let command_string = match args.is_empty() {
+            true => binary.clone(),
+            false => format!("{} {}", binary, args.join(" ")),
+        };
+        self.calls.lock().unwrap().push(command_string.clone());
+
+        match self.outputs.iter().find(|(output_binary, output_args, _)| {
+            *output_binary == binary && *output_args == args
+        }) {
+            Some((_, _, output)) => Ok(CommandOutput {
+                current_dir: current_dir_of(cmd),
+                ..output.clone()
+            }),
+            None => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!("no output programmed for {}", command_string)),
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(exit_code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    // wait status of a process which exited: the exit code is in the second byte
+    ExitStatus::from_raw((exit_code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(exit_code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(exit_code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::runner::{set_command_runner, DryRunRunner, MockRunner};
+    use crate::cmd::utilities::{exec, exec_capture, exec_with_output};
+    use crate::error::SimpleErrorKind;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mock_runner() {
+        let runner = MockRunner::new()
+            .on(
+                "helm",
+                &["list", "-A"],
+                MockRunner::output("nginx\nredis", "deprecated flag", 0),
+            )
+            .on("terraform", &["apply"], MockRunner::output("", "Error", 1));
+        set_command_runner(Some(Arc::new(runner)));
+
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        exec_with_output(
+            "helm",
+            vec!["list", "-A"],
+            |line| stdout.push(line.unwrap()),
+            |line| stderr.push(line.unwrap()),
+        )
+        .unwrap();
+        assert_eq!(stdout, vec!["nginx", "redis"]);
+        assert_eq!(stderr, vec!["deprecated flag"]);
+
+        let err = exec("terraform", vec!["apply"]).unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(1)));
+
+        let err = exec("terraform", vec!["destroy"]).unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::NotFound));
+
+        set_command_runner(None);
+    }
+
+    #[test]
+    fn test_dry_run_runner() {
+        set_command_runner(Some(Arc::new(DryRunRunner)));
+
+        // nothing is spawned, even a binary which does not exist succeeds
+        assert!(exec("qovery-nonexistent-binary", vec!["destroy"]).is_ok());
+        let output = exec_capture("sh", vec!["-c", "echo hello"], vec![("TOKEN", "abcd")]).unwrap();
+        assert_eq!(output.stdout, "");
+        assert!(output.status.success());
+
+        set_command_runner(None);
+    }
+}
@@ -129,6 +129,43 @@ pub struct KubernetesNode {
 pub struct KubernetesNodeStatus {
     pub allocatable: KubernetesNodeStatusResources,
     pub capacity: KubernetesNodeStatusResources,
+    #[serde(default)]
+    pub conditions: Vec<KubernetesNodeCondition>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesNodeCondition {
+    #[serde(rename = "type")]
+    pub condition_type: String,
+    pub status: String,
+}
+
+impl KubernetesNode {
+    /// true if the node has a `Ready` condition with a `True` status
+    pub fn is_ready(&self) -> bool {
+        self.status
+            .conditions
+            .iter()
+            .any(|x| x.condition_type == "Ready" && x.status == "True")
+    }
+}
+
+/// output of `kubectl version -o json`, the server version is missing if the cluster is unreachable
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesVersion {
+    pub client_version: KubernetesVersionInfo,
+    pub server_version: Option<KubernetesVersionInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesVersionInfo {
+    pub major: String,
+    /// may have a suffix on managed clusters, ex: "18+"
+    pub minor: String,
+    pub git_version: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
@@ -1,10 +1,14 @@
 use dirs::home_dir;
 
-use crate::cmd::utilities::exec_with_envs_and_output;
+use crate::cmd::command::CommandBuilder;
 use crate::constants::TF_PLUGIN_CACHE_DIR;
 use crate::error::{SimpleError, SimpleErrorKind};
+use crate::proxy::{with_proxy_envs, ProxyConfig};
 use retry::delay::Fixed;
+use regex::Regex;
 use retry::OperationResult;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
 
 fn terraform_exec_with_init_validate_plan(root_dir: &str) -> Result<(), SimpleError> {
     // terraform init
@@ -93,16 +97,248 @@ pub fn terraform_exec_with_init_plan_apply_destroy(root_dir: &str) -> Result<(),
 pub fn terraform_exec(root_dir: &str, args: Vec<&str>) -> Result<(), SimpleError> {
     let home_dir = home_dir().expect("Could not find $HOME");
     let tf_plugin_cache_dir = format!("{}/.terraform.d/plugin-cache", home_dir.to_str().unwrap());
+    let proxy_envs = ProxyConfig::from_env().envs();
 
-    exec_with_envs_and_output(
-        format!("{} terraform", root_dir).as_str(),
-        args,
-        vec![(TF_PLUGIN_CACHE_DIR, tf_plugin_cache_dir.as_str())],
-        |line: Result<String, std::io::Error>| {
-            info!("{}", line.unwrap());
-        },
-        |line: Result<String, std::io::Error>| {
-            error!("{}", line.unwrap());
-        },
-    )
+    // the lines are logged as they come, stdout as info and stderr as error
+    let result = CommandBuilder::new("terraform")
+        .args(args)
+        .envs(with_proxy_envs(
+            vec![(TF_PLUGIN_CACHE_DIR, tf_plugin_cache_dir.as_str())],
+            &proxy_envs,
+        ))
+        .current_dir(root_dir)
+        .log_lines(true)
+        .run();
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(mut err) => {
+            let stderr = err
+                .output
+                .as_ref()
+                .map(|output| output.stderr.clone())
+                .unwrap_or_default();
+
+            // only the kind changes, the captured output (stderr, exit status) is kept
+            if is_state_lock_error(stderr.as_str()) {
+                err.kind = SimpleErrorKind::StateLocked {
+                    lock_id: state_lock_id(stderr.as_str()),
+                };
+            }
+
+            Err(err)
+        }
+    }
+}
+
+/// select the terraform workspace `name` in `root_dir`, creating it if it does not exist yet,
+/// and return the name of the active workspace
+pub fn terraform_workspace_select_or_create(
+    root_dir: &str,
+    name: &str,
+    envs: Vec<(&str, &str)>,
+) -> Result<String, SimpleError> {
+    let terraform_workspace = |args: Vec<&str>| {
+        CommandBuilder::new("terraform")
+            .arg("workspace")
+            .args(args)
+            .envs(envs.clone())
+            .current_dir(root_dir)
+            .run()
+    };
+
+    match terraform_workspace(vec!["select", name]) {
+        Ok(_) => {}
+        Err(err) if is_missing_workspace_error(err.message.as_deref().unwrap_or_default()) => {
+            info!("terraform workspace {} does not exist, creating it", name);
+            terraform_workspace(vec!["new", name])?;
+        }
+        Err(err) => return Err(err),
+    };
+
+    let output = terraform_workspace(vec!["show"])?;
+    Ok(output.stdout.trim().to_string())
+}
+
+/// outputs of the terraform state in `root_dir`, deserialized into `T`: one field per output,
+/// holding its value (the `{"value": ..., "type": ..., "sensitive": ...}` envelope is unwrapped).
+/// Typically used after `terraform apply` to get the cluster endpoint, the generated names...
+pub fn terraform_output<T: DeserializeOwned>(
+    root_dir: &str,
+    envs: Vec<(&str, &str)>,
+) -> Result<T, SimpleError> {
+    let output = CommandBuilder::new("terraform")
+        .args(vec!["output", "-json"])
+        .envs(envs)
+        .current_dir(root_dir)
+        .run()?;
+
+    parse_terraform_outputs(root_dir, output.stdout.as_str())
+}
+
+fn parse_terraform_outputs<T: DeserializeOwned>(root_dir: &str, stdout: &str) -> Result<T, SimpleError> {
+    let outputs = match stdout.trim() {
+        "" => Map::new(),
+        json => serde_json::from_str::<Map<String, Value>>(json).map_err(|err| {
+            SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("unable to parse the terraform outputs of {}: {}", root_dir, err)),
+            )
+        })?,
+    };
+
+    if outputs.is_empty() {
+        return Err(SimpleError::new(
+            SimpleErrorKind::NotFound,
+            Some(format!(
+                "terraform returned no output for {}, is it applied and are the outputs declared?",
+                root_dir
+            )),
+        ));
+    }
+
+    let values = outputs
+        .into_iter()
+        .map(|(name, mut output)| {
+            let value = output.get_mut("value").map(Value::take).unwrap_or(Value::Null);
+            (name, value)
+        })
+        .collect::<Map<String, Value>>();
+
+    serde_json::from_value(Value::Object(values)).map_err(|err| {
+        SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("unexpected terraform outputs for {}: {}", root_dir, err)),
+        )
+    })
+}
+
+fn is_missing_workspace_error(stderr: &str) -> bool {
+    stderr.contains("Workspace \"") && stderr.contains("doesn't exist")
+}
+
+fn is_state_lock_error(stderr: &str) -> bool {
+    stderr.contains("Error acquiring the state lock") || stderr.contains("Error locking state")
+}
+
+/// id of the lock from the "Lock Info" terraform prints when the state is already locked
+fn state_lock_id(stderr: &str) -> Option<String> {
+    let lock_info = &stderr[stderr.find("Lock Info:")?..];
+    let id_regex = Regex::new(r"(?m)^\s*ID:\s+(\S+)").unwrap();
+
+    id_regex
+        .captures(lock_info)
+        .and_then(|captures| captures.get(1))
+        .map(|id| id.as_str().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::runner::{set_command_runner, MockRunner};
+    use crate::cmd::terraform::{
+        is_missing_workspace_error, is_state_lock_error, parse_terraform_outputs, state_lock_id,
+        terraform_exec,
+    };
+    use crate::error::SimpleErrorKind;
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_state_lock_id() {
+        let stderr = r#"
+Error: Error acquiring the state lock
+
+Error message: ConditionalCheckFailedException: The conditional request failed
+Lock Info:
+  ID:        a5b0e0ba-3f15-6cd6-ab9b-1f2a7e6f2c3d
+  Path:      qovery-terrafom-tfstates/z1234/main.tfstate
+  Operation: OperationTypeApply
+  Who:       engine@qovery
+  Version:   0.13.4
+  Created:   2020-10-14 08:16:02.123456 +0000 UTC
+  Info:
+"#;
+
+        assert!(is_state_lock_error(stderr));
+        assert_eq!(
+            state_lock_id(stderr),
+            Some("a5b0e0ba-3f15-6cd6-ab9b-1f2a7e6f2c3d".to_string())
+        );
+        assert!(!is_state_lock_error("Error: Invalid provider configuration"));
+    }
+
+    #[test]
+    fn test_state_locked_error_keeps_the_output() {
+        let stderr = "Error: Error acquiring the state lock\nLock Info:\n  ID:        a5b0e0ba\n";
+        set_command_runner(Some(Arc::new(MockRunner::new().on(
+            "terraform",
+            &["apply"],
+            MockRunner::output("", stderr, 1),
+        ))));
+
+        let err = terraform_exec("/tmp", vec!["apply"]).unwrap_err();
+        set_command_runner(None);
+
+        assert!(matches!(
+            err.kind,
+            SimpleErrorKind::StateLocked { lock_id: Some(ref lock_id) } if lock_id == "a5b0e0ba"
+        ));
+        assert_eq!(err.exit_code(), Some(1));
+        assert_eq!(err.output.unwrap().stderr, stderr);
+    }
+
+    #[test]
+    fn test_is_missing_workspace_error() {
+        assert!(is_missing_workspace_error(
+            r#"error while executing an internal command: Workspace "z1234" doesn't exist.
+
+You can create this workspace with the "new" subcommand."#
+        ));
+        assert!(!is_missing_workspace_error(
+            "error while executing an internal command: Error loading state: AccessDenied"
+        ));
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct ClusterOutputs {
+        kubernetes_cluster_endpoint: String,
+        node_pool_ids: Vec<String>,
+    }
+
+    #[test]
+    fn test_parse_terraform_outputs() {
+        let stdout = r#"{
+  "kubernetes_cluster_endpoint": {
+    "sensitive": false,
+    "type": "string",
+    "value": "https://1a2b3c.k8s.ondigitalocean.com"
+  },
+  "node_pool_ids": {
+    "sensitive": false,
+    "type": ["list", "string"],
+    "value": ["pool-1", "pool-2"]
+  }
+}"#;
+
+        let outputs = parse_terraform_outputs::<ClusterOutputs>("/tmp/cluster", stdout).unwrap();
+        assert_eq!(
+            outputs,
+            ClusterOutputs {
+                kubernetes_cluster_endpoint: "https://1a2b3c.k8s.ondigitalocean.com".to_string(),
+                node_pool_ids: vec!["pool-1".to_string(), "pool-2".to_string()],
+            }
+        );
+
+        for stdout in &["", "{}\n"] {
+            let err = parse_terraform_outputs::<ClusterOutputs>("/tmp/cluster", stdout).unwrap_err();
+            assert!(matches!(err.kind, SimpleErrorKind::NotFound));
+        }
+
+        let err = parse_terraform_outputs::<ClusterOutputs>(
+            "/tmp/cluster",
+            r#"{"node_pool_ids": {"value": []}}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Other));
+    }
 }
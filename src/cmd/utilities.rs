@@ -1,122 +1,449 @@
+use std::collections::VecDeque;
+use std::env;
 use std::ffi::OsStr;
-use std::io::Error;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::process::{Child, Command, Stdio};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, Once, OnceLock, RwLock};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use crate::cmd::child::{ChildGuard, DROP_GRACE_PERIOD};
+use crate::cmd::command::CommandBuilder;
+use crate::cmd::deadline::Deadline;
+use crate::cmd::output::{
+    bounded_output_channel, spawn_output_readers, Backpressure, OutputLine, PhaseDuration,
+    ProgressReporter, Stream,
+};
+use crate::cmd::runner::{
+    command_runner, dry_run_output, is_dry_run, CommandRunner, ProcessRunner,
+};
 use crate::error::{SimpleError, SimpleErrorKind};
+use crate::metrics::{
+    metrics, COMMAND_COUNT, COMMAND_DURATION_SECONDS, COMMAND_FAILURE_COUNT, RETRY_COUNT,
+};
+use crate::string::redact_secrets;
+use regex::Regex;
+use retry::delay::Fibonacci;
+use retry::OperationResult;
+use serde::{Serialize, Serializer};
 
-fn command<P>(binary: P, args: Vec<&str>, envs: Option<Vec<(&str, &str)>>) -> Command
+/// maximum number of output lines waiting to be handed to the output callbacks
+const OUTPUT_QUEUE_CAPACITY: usize = 10_000;
+/// delay before the second attempt of `exec_with_retry`, the next ones follow a Fibonacci sequence
+const EXEC_RETRY_FIRST_DELAY_MILLIS: u64 = 1000;
+/// last stderr lines of a streamed command kept for its error if it fails
+pub(crate) const COMMAND_ERROR_STDERR_MAX_LINES: usize = 100;
+/// time given to a timed out command to stop after SIGTERM, before it is killed
+pub const TIMEOUT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// once a timed out command is stopped, time to wait for its last output lines
+const TIMEOUT_OUTPUT_DRAIN_PERIOD: Duration = Duration::from_millis(500);
+
+static ALLOWED_BINARIES: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+/// restrict the binaries the engine is allowed to execute (e.g. terraform, kubectl, helm, docker).
+/// A binary called by its name (ex: "terraform", found in the PATH) is allowed if the name is part
+/// of the list, a binary called by its path only if that exact path is (ex: "/usr/bin/terraform"),
+/// so "/tmp/evil/terraform" is not allowed by "terraform".
+/// `None` (the default) lets any binary be executed.
+pub fn set_allowed_binaries(binaries: Option<Vec<&str>>) {
+    let mut allowed_binaries = ALLOWED_BINARIES.write().unwrap();
+    *allowed_binaries = binaries.map(|b| b.iter().map(|x| x.to_string()).collect());
+}
+
+/// env vars whose name contains one of these (case insensitive) have their value masked in the logs
+pub const DEFAULT_SENSITIVE_ENV_KEYS: &[&str] = &["SECRET", "KEY", "TOKEN", "PASSWORD"];
+
+static SENSITIVE_ENV_KEYS: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+/// compiled once, see `parse_version`
+static VERSION_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// replace the patterns of `DEFAULT_SENSITIVE_ENV_KEYS`: the value of an env var is logged as `***`
+/// if its name contains one of `keys` (case insensitive). `None` restores the defaults.
+/// The commands still get the real values.
+pub fn set_sensitive_env_keys(keys: Option<Vec<&str>>) {
+    let mut sensitive_env_keys = SENSITIVE_ENV_KEYS.write().unwrap();
+    *sensitive_env_keys = keys.map(|k| k.iter().map(|x| x.to_uppercase()).collect());
+}
+
+pub fn is_sensitive_env_key(key: &str) -> bool {
+    match SENSITIVE_ENV_KEYS.read().unwrap().as_ref() {
+        Some(keys) => matches_sensitive_key(key, keys),
+        None => matches_sensitive_key(key, DEFAULT_SENSITIVE_ENV_KEYS),
+    }
+}
+
+fn matches_sensitive_key<S: AsRef<str>>(key: &str, sensitive_keys: &[S]) -> bool {
+    let key = key.to_uppercase();
+    sensitive_keys
+        .iter()
+        .any(|x| key.contains(x.as_ref().to_uppercase().as_str()))
+}
+
+pub(crate) fn check_binary_is_allowed(binary: &str) -> Result<(), SimpleError> {
+    let allowed_binaries = ALLOWED_BINARIES.read().unwrap();
+
+    let allowed_binaries = match allowed_binaries.as_ref() {
+        Some(allowed_binaries) => allowed_binaries,
+        None => return Ok(()),
+    };
+
+    if is_binary_allowed(binary, allowed_binaries) {
+        return Ok(());
+    }
+
+    Err(SimpleError::new(
+        SimpleErrorKind::Other,
+        Some(format!(
+            "binary {} is not part of the allowed binaries ({})",
+            binary,
+            allowed_binaries.join(", ")
+        )),
+    ))
+}
+
+fn is_binary_allowed<S: AsRef<str>>(binary: &str, allowed_binaries: &[S]) -> bool {
+    allowed_binaries.iter().any(|x| x.as_ref() == binary)
+}
+
+/// make a write on a closed pipe (ex: an output consumer that went away) fail with a recoverable
+/// `BrokenPipe` error instead of killing the whole engine process with SIGPIPE.
+/// Rust binaries already do so at startup, but the engine may be embedded in a host that does not.
+/// The spawned commands are not affected: the default SIGPIPE disposition is restored in the child before exec.
+#[cfg(unix)]
+pub(crate) fn ignore_sigpipe() {
+    static IGNORE_SIGPIPE: Once = Once::new();
+
+    IGNORE_SIGPIPE.call_once(|| unsafe {
+        if libc::signal(libc::SIGPIPE, libc::SIG_IGN) == libc::SIG_ERR {
+            error!(
+                "unable to ignore SIGPIPE: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub(crate) fn ignore_sigpipe() {}
+
+/// name the current thread after the engine task it runs (ex: "deploy-app-123"), so it shows up
+/// in `ps -L` and `top -H`. The commands spawned keep their own name, tag them with `CommandBuilder::task`.
+/// Linux limits the name to 15 bytes, a longer one is truncated. No-op on the other platforms.
+#[cfg(target_os = "linux")]
+pub fn set_current_thread_name(name: &str) {
+    let mut thread_name = name
+        .bytes()
+        .filter(|x| *x != 0)
+        .take(15)
+        .collect::<Vec<u8>>();
+    thread_name.push(0);
+
+    unsafe {
+        if libc::prctl(
+            libc::PR_SET_NAME,
+            thread_name.as_ptr() as libc::c_ulong,
+            0,
+            0,
+            0,
+        ) != 0
+        {
+            warn!(
+                "unable to set the thread name to {}: {}",
+                name,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_current_thread_name(_name: &str) {}
+
+/// `binary` is the whole program path, it may contain whitespaces. The command runs in `current_dir`
+/// if given, in the engine current directory otherwise.
+pub(crate) fn command<P>(
+    binary: P,
+    args: Vec<&str>,
+    envs: Option<Vec<(&str, &str)>>,
+    current_dir: Option<&Path>,
+) -> Result<Command, SimpleError>
 where
     P: AsRef<Path>,
 {
-    let s_binary = binary
-        .as_ref()
-        .to_str()
-        .unwrap()
-        .split_whitespace()
-        .map(|x| x.to_string())
-        .collect::<Vec<_>>();
+    let binary = binary.as_ref();
+    if binary.as_os_str().is_empty() {
+        return Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some("no binary to execute"),
+        ));
+    }
 
-    let (current_dir, _binary) = if s_binary.len() == 1 {
-        (None, s_binary.first().unwrap().clone())
-    } else {
-        (
-            Some(s_binary.first().unwrap().clone()),
-            s_binary.get(1).unwrap().clone(),
-        )
-    };
+    check_binary_is_allowed(binary.to_string_lossy().as_ref())?;
+    ignore_sigpipe();
 
-    let mut cmd = Command::new(&_binary);
+    let mut cmd = Command::new(binary);
 
     cmd.args(&args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    if current_dir.is_some() {
-        cmd.current_dir(current_dir.unwrap());
+    if let Some(current_dir) = current_dir {
+        cmd.current_dir(current_dir);
     }
 
-    if envs.is_some() {
-        envs.unwrap().into_iter().for_each(|(k, v)| {
+    if let Some(envs) = envs {
+        envs.into_iter().for_each(|(k, v)| {
             cmd.env(k, v);
         });
     }
 
-    cmd
+    Ok(cmd)
 }
 
-pub fn exec<P>(binary: P, args: Vec<&str>) -> Result<(), SimpleError>
+/// directory the command will run in: its current directory if set, resolved against the engine one,
+/// or the engine current directory
+pub(crate) fn current_dir_of(cmd: &Command) -> PathBuf {
+    let engine_current_dir = env::current_dir().unwrap_or_default();
+    match cmd.get_current_dir() {
+        Some(current_dir) => engine_current_dir.join(current_dir),
+        None => engine_current_dir,
+    }
+}
+
+pub(crate) fn log_command(command_string: &str, cmd: &Command) {
+    info!(
+        "command: {} (current directory: {})",
+        command_string,
+        current_dir_of(cmd).display()
+    );
+}
+
+/// spawn the command, failing with `SimpleErrorKind::SpawnFailed` if it can't be started
+/// (ex: the binary is not installed or not executable)
+pub(crate) fn spawn(cmd: &mut Command) -> Result<Child, SimpleError> {
+    cmd.spawn().map_err(|err| spawn_error(cmd, err))
+}
+
+pub(crate) fn spawn_error(cmd: &Command, err: Error) -> SimpleError {
+    spawn_failed(binary_name(Path::new(cmd.get_program())), err)
+}
+
+pub(crate) fn spawn_failed(binary: String, err: Error) -> SimpleError {
+    let message = format!("unable to start {}: {}", binary, err);
+
+    SimpleError::new(SimpleErrorKind::SpawnFailed { binary }, Some(message))
+}
+
+/// name of the binary without its path
+pub(crate) fn binary_name(binary: &Path) -> String {
+    binary
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| binary.to_string_lossy().to_string())
+}
+
+/// run the command, log how long it took and how it ended, and record its count, failure and duration metrics
+fn with_command_metrics<T, F>(binary_name: &str, run: F) -> Result<T, SimpleError>
 where
-    P: AsRef<Path>,
+    F: FnOnce() -> Result<T, SimpleError>,
 {
-    let command_string = command_to_string(binary.as_ref(), &args);
-    info!("command: {}", command_string.as_str());
+    let started_at = Instant::now();
+    let result = run();
+    record_command_metrics(binary_name, started_at.elapsed(), &result);
+
+    result
+}
+
+/// log how long the command took and how it ended, and record its count, failure and duration metrics
+pub(crate) fn record_command_metrics<T>(
+    binary_name: &str,
+    elapsed: Duration,
+    result: &Result<T, SimpleError>,
+) {
+    let labels = [("binary", binary_name)];
 
-    let exit_status = match command(binary, args, None).spawn().unwrap().wait() {
-        Ok(x) => x,
-        Err(err) => return Err(SimpleError::from(err)),
+    let outcome = match result {
+        Ok(_) => "succeeded".to_string(),
+        Err(err) => format!("failed ({})", err.kind),
     };
+    info!(
+        "command {} {} in {:.3}s",
+        binary_name,
+        outcome,
+        elapsed.as_secs_f64()
+    );
 
-    if exit_status.success() {
-        return Ok(());
+    let metrics = metrics();
+    metrics.increment(COMMAND_COUNT, 1, &labels);
+    if result.is_err() {
+        metrics.increment(COMMAND_FAILURE_COUNT, 1, &labels);
     }
+    metrics.observe(COMMAND_DURATION_SECONDS, elapsed.as_secs_f64(), &labels);
+}
 
-    Err(SimpleError::new(
-        SimpleErrorKind::Command(exit_status),
-        Some("error while executing an internal command"),
-    ))
+/// same as `CommandBuilder::new(binary).args(args).run()`, without the output
+pub fn exec<P>(binary: P, args: Vec<&str>) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+{
+    // the output is buffered so stderr can be part of the error if the command fails
+    CommandBuilder::new(binary).args(args).run().map(|_| ())
+}
+
+/// `envs` can be owned (ex: `Vec<(String, String)>`) as well as borrowed
+pub fn exec_with_envs<P, E, K, V>(binary: P, args: Vec<&str>, envs: E) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let envs = envs
+        .into_iter()
+        .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string()))
+        .collect::<Vec<_>>();
+
+    // the output is buffered so stderr can be part of the error if the command fails
+    CommandBuilder::new(binary)
+        .args(args)
+        .envs(envs)
+        .run()
+        .map(|_| ())
 }
 
-pub fn exec_with_envs<P>(
+/// same as `exec_with_envs`, but the command does not inherit the engine environment:
+/// it only gets `PATH`, `HOME` and `envs`, for reproducible runs (ex: terraform).
+/// `PATH` and `HOME` can be overridden by `envs`.
+pub fn exec_with_clean_envs<P, E, K, V>(
     binary: P,
     args: Vec<&str>,
-    envs: Vec<(&str, &str)>,
+    envs: E,
 ) -> Result<(), SimpleError>
 where
     P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
 {
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
     let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
-    info!("command: {}", command_string.as_str());
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, None, None)?;
+    clear_envs(&mut cmd);
+    cmd.envs(envs);
+    log_command(command_string.as_str(), &cmd);
 
-    let exit_status = match command(binary, args, Some(envs)).spawn().unwrap().wait() {
-        Ok(x) => x,
-        Err(err) => return Err(SimpleError::from(err)),
-    };
+    with_command_metrics(binary_name.as_str(), move || {
+        // the output is buffered so stderr can be part of the error if the command fails
+        capture(cmd).map(|_| ())
+    })
+}
 
-    if exit_status.success() {
-        return Ok(());
+fn borrow_envs<K, V>(envs: &[(K, V)]) -> Vec<(&str, &str)>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    envs.iter().map(|(k, v)| (k.as_ref(), v.as_ref())).collect()
+}
+
+/// remove the inherited envs of the command, except `PATH` so the binaries are still found,
+/// and `HOME` where most tools keep their configuration
+fn clear_envs(cmd: &mut Command) {
+    cmd.env_clear();
+
+    if let Some(path) = env::var_os("PATH") {
+        cmd.env("PATH", path);
     }
 
-    Err(SimpleError::new(
-        SimpleErrorKind::Command(exit_status),
-        Some("error while executing an internal command"),
-    ))
+    if let Some(home) = dirs::home_dir() {
+        cmd.env("HOME", home);
+    }
 }
 
-fn _with_output<F, X>(mut child: Child, mut stdout_output: F, mut stderr_output: X) -> Child
+/// Forward stdout and stderr lines of the child to the callbacks, on the current thread.
+///
+/// Both streams are read concurrently on dedicated threads and queued, so a slow callback (ex: a slow
+/// log consumer) does not stop the child output from being drained until `OUTPUT_QUEUE_CAPACITY` lines
+/// are waiting. Then the readers wait for the callbacks: no line is dropped, as callers parse the
+/// whole output (ex: kubectl JSON). Use `exec_streaming` with `Backpressure::DropOldest` to drop lines instead.
+///
+/// The last `COMMAND_ERROR_STDERR_MAX_LINES` stderr lines are returned along with the child,
+/// to be part of the error if the command fails.
+/// The child is killed if a callback panics, instead of being left running.
+/// `progress` is given every line before the callbacks, and is also called while no line comes.
+fn _with_output<F, X>(
+    mut child: ChildGuard,
+    mut stdout_output: F,
+    mut stderr_output: X,
+    mut progress: Option<ProgressReporter>,
+) -> (ChildGuard, String)
 where
     F: FnMut(Result<String, Error>),
     X: FnMut(Result<String, Error>),
 {
-    let stdout_reader = BufReader::new(child.stdout.as_mut().unwrap());
-    for line in stdout_reader.lines() {
-        stdout_output(line);
+    let (send, close, receiver) =
+        bounded_output_channel(OUTPUT_QUEUE_CAPACITY, Backpressure::Block);
+    let readers = spawn_output_readers(child.child_mut(), send);
+
+    thread::spawn(move || {
+        for reader in readers {
+            let _ = reader.join();
+        }
+        close();
+    });
+
+    let mut stderr_tail = VecDeque::with_capacity(COMMAND_ERROR_STDERR_MAX_LINES);
+    loop {
+        let line = match progress.as_ref().and_then(|x| x.next_report_in()) {
+            Some(next_report_in) => match receiver.recv_timeout(next_report_in) {
+                Ok(line) => line,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Some(progress) = progress.as_mut() {
+                        progress.tick();
+                    }
+                    continue;
+                }
+            },
+            None => match receiver.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            },
+        };
+
+        if let Some(progress) = progress.as_mut() {
+            progress.on_line(&line);
+        }
+
+        match line.stream {
+            Stream::Stdout => stdout_output(Ok(line.text)),
+            Stream::Stderr => {
+                push_stderr_tail(&mut stderr_tail, line.text.as_str());
+                stderr_output(Ok(line.text))
+            }
+        }
     }
 
-    let stderr_reader = BufReader::new(child.stderr.as_mut().unwrap());
-    for line in stderr_reader.lines() {
-        stderr_output(line);
+    if let Some(progress) = progress.as_mut() {
+        progress.finish();
     }
 
-    child
+    (child, Vec::from(stderr_tail).join("\n"))
 }
 
 pub fn exec_with_output<P, F, X>(
     binary: P,
     args: Vec<&str>,
-    stdout_output: F,
-    stderr_output: X,
+    mut stdout_output: F,
+    mut stderr_output: X,
 ) -> Result<(), SimpleError>
 where
     P: AsRef<Path>,
@@ -124,122 +451,1598 @@ where
     X: FnMut(Result<String, Error>),
 {
     let command_string = command_to_string(binary.as_ref(), &args);
-    info!("command: {}", command_string.as_str());
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, None, None)?;
+    log_command(command_string.as_str(), &cmd);
 
-    let mut child = _with_output(
-        command(binary, args, None).spawn().unwrap(),
-        stdout_output,
-        stderr_output,
-    );
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            let output = runner.run(&mut cmd)?;
+            output
+                .stdout
+                .lines()
+                .for_each(|x| stdout_output(Ok(x.to_string())));
+            output
+                .stderr
+                .lines()
+                .for_each(|x| stderr_output(Ok(x.to_string())));
+            return check_output(output).map(|_| ());
+        }
 
-    let exit_status = match child.wait() {
-        Ok(x) => x,
-        Err(err) => return Err(SimpleError::from(err)),
-    };
+        let (mut child, stderr) = _with_output(
+            ChildGuard::new(spawn(&mut cmd)?),
+            stdout_output,
+            stderr_output,
+            None,
+        );
 
-    if exit_status.success() {
-        return Ok(());
-    }
+        let exit_status = match child.wait() {
+            Ok(x) => x,
+            Err(err) => return Err(SimpleError::from(err)),
+        };
 
-    Err(SimpleError::new(
-        SimpleErrorKind::Command(exit_status),
-        Some("error while executing an internal command"),
-    ))
+        if exit_status.success() {
+            return Ok(());
+        }
+
+        Err(command_error(exit_status, stderr.as_str()))
+    })
 }
 
-pub fn exec_with_envs_and_output<P, F, X>(
+/// `envs` can be owned (ex: `Vec<(String, String)>`) as well as borrowed
+pub fn exec_with_envs_and_output<P, E, K, V, F, X>(
     binary: P,
     args: Vec<&str>,
-    envs: Vec<(&str, &str)>,
+    envs: E,
     stdout_output: F,
     stderr_output: X,
 ) -> Result<(), SimpleError>
 where
     P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
     F: FnMut(Result<String, Error>),
     X: FnMut(Result<String, Error>),
 {
-    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
-    info!("command: {}", command_string.as_str());
+    with_envs_and_output(binary, args, envs, stdout_output, stderr_output, None)
+}
 
-    let mut child = _with_output(
-        command(binary, args, Some(envs)).spawn().unwrap(),
+/// same as `exec_with_envs_and_output`, reporting the lines read and the time elapsed to `progress`
+/// while the command runs (ex: to show "terraform apply: 1200 lines, 3m elapsed" instead of silence)
+/// ```no_run
+/// use qovery_engine::cmd::output::ProgressReporter;
+/// use qovery_engine::cmd::utilities::exec_with_envs_and_progress;
+/// use std::time::Duration;
+///
+/// let result = exec_with_envs_and_progress(
+///     "terraform",
+///     vec!["apply", "-auto-approve", "tf_plan"],
+///     vec![("TF_IN_AUTOMATION", "true")],
+///     |line| println!("{}", line.unwrap()),
+///     |line| eprintln!("{}", line.unwrap()),
+///     ProgressReporter::new(500, Duration::from_secs(30), |progress| {
+///         println!(
+///             "terraform apply: {} lines, {}s elapsed",
+///             progress.stdout_lines + progress.stderr_lines,
+///             progress.elapsed.as_secs()
+///         )
+///     }),
+/// );
+/// ```
+pub fn exec_with_envs_and_progress<P, E, K, V, F, X>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    stdout_output: F,
+    stderr_output: X,
+    progress: ProgressReporter,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+    F: FnMut(Result<String, Error>),
+    X: FnMut(Result<String, Error>),
+{
+    with_envs_and_output(
+        binary,
+        args,
+        envs,
         stdout_output,
         stderr_output,
+        Some(progress),
+    )
+}
+
+fn with_envs_and_output<P, E, K, V, F, X>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    mut stdout_output: F,
+    mut stderr_output: X,
+    mut progress: Option<ProgressReporter>,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+    F: FnMut(Result<String, Error>),
+    X: FnMut(Result<String, Error>),
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, Some(envs), None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            let output = runner.run(&mut cmd)?;
+            output.stdout.lines().for_each(|x| {
+                report_line(&mut progress, Stream::Stdout, x);
+                stdout_output(Ok(x.to_string()))
+            });
+            output.stderr.lines().for_each(|x| {
+                report_line(&mut progress, Stream::Stderr, x);
+                stderr_output(Ok(x.to_string()))
+            });
+            if let Some(progress) = progress.as_mut() {
+                progress.finish();
+            }
+            return check_output(output).map(|_| ());
+        }
+
+        let (mut child, stderr) = _with_output(
+            ChildGuard::new(spawn(&mut cmd)?),
+            stdout_output,
+            stderr_output,
+            progress,
+        );
+
+        let exit_status = match child.wait() {
+            Ok(x) => x,
+            Err(err) => return Err(SimpleError::from(err)),
+        };
+
+        if exit_status.success() {
+            return Ok(());
+        }
+
+        Err(command_error(exit_status, stderr.as_str()))
+    })
+}
+
+/// count a line of the output of a mocked command (see `CommandRunner`)
+fn report_line(progress: &mut Option<ProgressReporter>, stream: Stream, text: &str) {
+    if let Some(progress) = progress.as_mut() {
+        progress.on_line(&OutputLine::new(stream, text.to_string()));
+    }
+}
+
+/// same as `exec_with_envs`, but the command is run up to `max_attempts` times until it succeeds,
+/// waiting between attempts following a Fibonacci sequence (1s, 1s, 2s, 3s...).
+/// Any failure is retried (non-zero exit, binary not started), for flaky cloud CLI calls.
+/// Once the attempts are exhausted, the error of the last one is returned.
+pub fn exec_with_retry<P, E, K, V>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    max_attempts: usize,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let binary_name = binary_name(binary.as_ref());
+    let max_attempts = max_attempts.max(1);
+    let mut attempt = 0;
+
+    let result = retry::retry(
+        Fibonacci::from_millis(EXEC_RETRY_FIRST_DELAY_MILLIS).take(max_attempts - 1),
+        || {
+            attempt += 1;
+            info!("{} attempt {}/{}", binary_name, attempt, max_attempts);
+
+            match exec_with_envs(binary.as_ref(), args.clone(), envs.clone()) {
+                Ok(_) => OperationResult::Ok(()),
+                Err(err) => {
+                    warn!(
+                        "{} attempt {}/{} failed: {}",
+                        binary_name,
+                        attempt,
+                        max_attempts,
+                        err.message.as_deref().unwrap_or_default()
+                    );
+                    if attempt < max_attempts {
+                        metrics().increment(RETRY_COUNT, 1, &[("operation", "exec_with_retry")]);
+                    }
+                    OperationResult::Retry(err)
+                }
+            }
+        },
     );
 
-    let exit_status = match child.wait() {
-        Ok(x) => x,
-        Err(err) => return Err(SimpleError::from(err)),
+    match result {
+        Ok(_) => Ok(()),
+        Err(retry::Error::Operation { error, .. }) => Err(error),
+        Err(retry::Error::Internal(err)) => {
+            Err(SimpleError::new(SimpleErrorKind::Other, Some(err)))
+        }
+    }
+}
+
+/// same as `exec_with_envs_and_output`, but the command is terminated once `timeout` is elapsed:
+/// it gets SIGTERM, then SIGKILL if it is still running after `TIMEOUT_GRACE_PERIOD`, and a
+/// `SimpleErrorKind::Timeout` error naming the command is returned.
+/// The lines written before the timeout are handed to the callbacks before the error is returned.
+pub fn exec_with_timeout<P, E, K, V, F, X>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    timeout: Duration,
+    mut stdout_output: F,
+    mut stderr_output: X,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+    F: FnMut(Result<String, Error>),
+    X: FnMut(Result<String, Error>),
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
+    let short_command_string = command_to_string(binary.as_ref(), &args);
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, Some(envs), None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            let output = runner.run(&mut cmd)?;
+            output
+                .stdout
+                .lines()
+                .for_each(|x| stdout_output(Ok(x.to_string())));
+            output
+                .stderr
+                .lines()
+                .for_each(|x| stderr_output(Ok(x.to_string())));
+            return check_output(output).map(|_| ());
+        }
+
+        let deadline = Deadline::after(timeout);
+        let mut child = ChildGuard::new(spawn(&mut cmd)?);
+
+        let (tx, rx) = mpsc::channel();
+        let readers = spawn_output_readers(child.child_mut(), move |line| {
+            let _ = tx.send(line);
+        });
+
+        let mut stderr_tail = VecDeque::with_capacity(COMMAND_ERROR_STDERR_MAX_LINES);
+        let mut forward = |line: OutputLine| match line.stream {
+            Stream::Stdout => stdout_output(Ok(line.text)),
+            Stream::Stderr => {
+                push_stderr_tail(&mut stderr_tail, line.text.as_str());
+                stderr_output(Ok(line.text))
+            }
+        };
+
+        loop {
+            match rx.recv_timeout(deadline.remaining()) {
+                Ok(line) => forward(line),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "command {} timed out after {:?}, terminating it",
+                        short_command_string, timeout
+                    );
+                    child.terminate(TIMEOUT_GRACE_PERIOD)?;
+
+                    // hand what the command wrote before it stopped. A process it forked
+                    // may still hold its outputs open, so don't wait for them to be closed
+                    while let Ok(line) = rx.recv_timeout(TIMEOUT_OUTPUT_DRAIN_PERIOD) {
+                        forward(line);
+                    }
+
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Timeout(timeout),
+                        Some(format!(
+                            "command {} timed out after {} seconds",
+                            short_command_string,
+                            timeout.as_secs()
+                        )),
+                    ));
+                }
+            }
+        }
+
+        for reader in readers {
+            let _ = reader.join();
+        }
+
+        let exit_status = child.wait()?;
+        if exit_status.success() {
+            return Ok(());
+        }
+
+        Err(command_error(
+            exit_status,
+            Vec::from(stderr_tail).join("\n").as_str(),
+        ))
+    })
+}
+
+/// full output of an executed command.
+/// It is serialized with the exit code as `status`, use `redacted` first to mask the secrets it may contain.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    #[serde(serialize_with = "serialize_exit_status")]
+    pub status: ExitStatus,
+    /// directory the command ran in
+    pub current_dir: PathBuf,
+    /// time spent in each phase of the command, if phases were configured
+    pub phases: Vec<PhaseDuration>,
+    /// environment variables the command ran with, if recorded (see `CommandBuilder::record_envs`)
+    pub envs: Vec<(String, String)>,
+}
+
+impl CommandOutput {
+    /// copy of the output with the secrets replaced by `****` in stdout and stderr
+    pub fn redacted(&self, secrets: &[&str]) -> CommandOutput {
+        CommandOutput {
+            stdout: redact_secrets(self.stdout.as_str(), secrets),
+            stderr: redact_secrets(self.stderr.as_str(), secrets),
+            envs: self
+                .envs
+                .iter()
+                .map(|(k, v)| (k.clone(), redact_secrets(v.as_str(), secrets)))
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// exit code, or null if the command has been killed by a signal
+fn serialize_exit_status<S: Serializer>(
+    status: &ExitStatus,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    status.code().serialize(serializer)
+}
+
+/// execute the command and capture its whole stdout and stderr.
+/// If it fails, the captured output is attached to the error (`SimpleError::output`).
+pub fn exec_capture<P, E, K, V>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+) -> Result<CommandOutput, SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
+    let cmd = command(binary, args, Some(envs), None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    run_and_capture(cmd)
+}
+
+/// execute the command and return its stdout and stderr merged into one string, in the order the lines
+/// are written, with its exit status. A non zero exit status is not an error: the output is returned anyway.
+pub fn exec_combined_output<P>(
+    binary: P,
+    args: Vec<&str>,
+) -> Result<(String, ExitStatus), SimpleError>
+where
+    P: AsRef<Path>,
+{
+    let command_string = command_to_string(binary.as_ref(), &args);
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, None, None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            let output = runner.run(&mut cmd)?;
+            return Ok((format!("{}{}", output.stdout, output.stderr), output.status));
+        }
+
+        let mut output = String::new();
+        let status = forward_combined_lines(&mut cmd, |line| {
+            output.push_str(line.text.as_str());
+            output.push('\n');
+        })?;
+
+        Ok((output, status))
+    })
+}
+
+/// same as `exec_with_envs_and_output`, but stdout and stderr lines are given to a single callback,
+/// in the order they are read, each one tagged with its stream and the time it was read.
+/// It keeps a build log interleaved as written while still telling stderr from stdout.
+pub fn exec_with_envs_and_combined_output<P, E, K, V, F>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    mut on_line: F,
+) -> Result<(), SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+    F: FnMut(OutputLine),
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, Some(envs), None)?;
+    log_command(command_string.as_str(), &cmd);
+
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            let output = runner.run(&mut cmd)?;
+            output
+                .stdout
+                .lines()
+                .for_each(|x| on_line(OutputLine::new(Stream::Stdout, x.to_string())));
+            output
+                .stderr
+                .lines()
+                .for_each(|x| on_line(OutputLine::new(Stream::Stderr, x.to_string())));
+            return check_output(output).map(|_| ());
+        }
+
+        let mut stderr_tail = VecDeque::with_capacity(COMMAND_ERROR_STDERR_MAX_LINES);
+        let status = forward_combined_lines(&mut cmd, |line| {
+            if line.stream == Stream::Stderr {
+                push_stderr_tail(&mut stderr_tail, line.text.as_str());
+            }
+            on_line(line)
+        })?;
+
+        if status.success() {
+            return Ok(());
+        }
+
+        Err(command_error(
+            status,
+            Vec::from(stderr_tail).join("\n").as_str(),
+        ))
+    })
+}
+
+/// spawn the command and give its stdout and stderr lines to `on_line` as they are read,
+/// on the current thread, then wait for it to exit
+fn forward_combined_lines<F>(cmd: &mut Command, mut on_line: F) -> Result<ExitStatus, SimpleError>
+where
+    F: FnMut(OutputLine),
+{
+    let mut child = ChildGuard::new(spawn(cmd)?);
+
+    let (tx, rx) = mpsc::channel();
+    let readers = spawn_output_readers(child.child_mut(), move |line| {
+        let _ = tx.send(line);
+    });
+
+    for line in rx {
+        on_line(line);
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    Ok(child.wait()?)
+}
+
+/// same as `exec_capture`, with `input` written to the command stdin, which is then closed
+/// (ex: a manifest for `kubectl apply -f -`).
+/// The input is written from its own thread while the output is read, so neither side blocks the other.
+pub fn exec_with_stdin<P, E, K, V>(
+    binary: P,
+    args: Vec<&str>,
+    envs: E,
+    input: &[u8],
+) -> Result<CommandOutput, SimpleError>
+where
+    P: AsRef<Path>,
+    E: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let envs = envs.into_iter().collect::<Vec<_>>();
+    let envs = borrow_envs(&envs);
+    let command_string = command_with_envs_to_string(binary.as_ref(), &args, &envs);
+    let binary_name = binary_name(binary.as_ref());
+    let mut cmd = command(binary, args, Some(envs), None)?;
+    cmd.stdin(Stdio::piped());
+    log_command(command_string.as_str(), &cmd);
+
+    with_command_metrics(binary_name.as_str(), move || {
+        if let Some(runner) = command_runner() {
+            return runner.run(&mut cmd).and_then(check_output);
+        }
+
+        let current_dir = current_dir_of(&cmd);
+        let mut child = ChildGuard::new(spawn(&mut cmd)?);
+
+        let writer = write_stdin(child.child_mut(), input.to_vec());
+        let output = child.wait_with_output()?;
+        join_stdin_writer(writer)?;
+
+        check_output(CommandOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            status: output.status,
+            current_dir,
+            phases: vec![],
+            envs: vec![],
+        })
+    })
+}
+
+pub(crate) fn run_and_capture(cmd: Command) -> Result<CommandOutput, SimpleError> {
+    let binary_name = binary_name(Path::new(cmd.get_program()));
+    with_command_metrics(binary_name.as_str(), move || capture(cmd))
+}
+
+fn capture(mut cmd: Command) -> Result<CommandOutput, SimpleError> {
+    let output = match command_runner() {
+        Some(runner) => runner.run(&mut cmd)?,
+        None => ProcessRunner.run(&mut cmd)?,
     };
+    check_output(output)
+}
 
-    if exit_status.success() {
-        return Ok(());
+/// write `input` on the stdin of the child (spawned with a piped stdin) from a dedicated thread,
+/// so a command writing a lot of output before reading its whole input can't deadlock
+fn write_stdin(child: &mut Child, input: Vec<u8>) -> Option<JoinHandle<std::io::Result<()>>> {
+    child.stdin.take().map(|mut stdin| {
+        // stdin is closed once dropped, at the end of the thread
+        thread::spawn(move || stdin.write_all(&input))
+    })
+}
+
+fn join_stdin_writer(writer: Option<JoinHandle<std::io::Result<()>>>) -> Result<(), SimpleError> {
+    if let Some(writer) = writer {
+        match writer.join() {
+            Ok(Err(err)) if err.kind() != ErrorKind::BrokenPipe => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!("unable to write the command stdin: {}", err)),
+                ))
+            }
+            // the command exited without reading its whole input, its exit status tells if it is an error
+            _ => {}
+        }
     }
 
-    Err(SimpleError::new(
-        SimpleErrorKind::Command(exit_status),
-        Some("error while executing an internal command"),
-    ))
+    Ok(())
+}
+
+/// same as `run_and_capture`, but `on_line` is called with each line as soon as the command writes it,
+/// and the line it returns is captured instead (`None` to drop the line).
+/// `on_line` is always called from the current thread.
+/// If a deadline is given, the command is stopped once it is exceeded (SIGTERM, then SIGKILL after
+/// `TIMEOUT_GRACE_PERIOD`) and a `Timeout` error is returned.
+/// If `stdin` is given, it is written on the command stdin, which is closed afterwards.
+pub(crate) fn run_and_capture_lines<F>(
+    cmd: Command,
+    deadline: Option<Deadline>,
+    stdin: Option<Vec<u8>>,
+    progress: Option<ProgressReporter>,
+    on_line: F,
+) -> Result<CommandOutput, SimpleError>
+where
+    F: FnMut(OutputLine) -> Option<OutputLine>,
+{
+    let binary_name = binary_name(Path::new(cmd.get_program()));
+    with_command_metrics(binary_name.as_str(), move || {
+        capture_lines(cmd, deadline, stdin, progress, on_line)
+    })
+}
+
+fn capture_lines<F>(
+    mut cmd: Command,
+    deadline: Option<Deadline>,
+    stdin: Option<Vec<u8>>,
+    mut progress: Option<ProgressReporter>,
+    mut on_line: F,
+) -> Result<CommandOutput, SimpleError>
+where
+    F: FnMut(OutputLine) -> Option<OutputLine>,
+{
+    if let Some(deadline) = deadline.as_ref() {
+        deadline.check()?;
+    }
+
+    if let Some(runner) = command_runner() {
+        let mut output = runner.run(&mut cmd)?;
+        let lines = output
+            .stdout
+            .lines()
+            .map(|x| (Stream::Stdout, x.to_string()))
+            .chain(
+                output
+                    .stderr
+                    .lines()
+                    .map(|x| (Stream::Stderr, x.to_string())),
+            )
+            .collect::<Vec<_>>();
+
+        output.stdout.clear();
+        output.stderr.clear();
+        for (stream, text) in lines {
+            report_line(&mut progress, stream, text.as_str());
+            if let Some(line) = on_line(OutputLine::new(stream, text)) {
+                let captured = match line.stream {
+                    Stream::Stdout => &mut output.stdout,
+                    Stream::Stderr => &mut output.stderr,
+                };
+                captured.push_str(line.text.as_str());
+                captured.push('\n');
+            }
+        }
+
+        if let Some(progress) = progress.as_mut() {
+            progress.finish();
+        }
+        return check_output(output);
+    }
+
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+
+    let current_dir = current_dir_of(&cmd);
+    let mut child = ChildGuard::new(spawn(&mut cmd)?);
+    let writer = stdin.and_then(|input| write_stdin(child.child_mut(), input));
+
+    let (tx, rx) = mpsc::channel();
+    let readers = spawn_output_readers(child.child_mut(), move |line| {
+        let _ = tx.send(line);
+    });
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    loop {
+        // wake up at the deadline or at the next progress report, whichever comes first
+        let wait = deadline
+            .as_ref()
+            .map(|x| x.remaining())
+            .into_iter()
+            .chain(progress.as_ref().and_then(|x| x.next_report_in()))
+            .min();
+
+        let line = match wait {
+            Some(wait) => match rx.recv_timeout(wait) {
+                Ok(line) => line,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Some(deadline) = deadline.as_ref().filter(|x| x.is_exceeded()) {
+                        // the readers return once the stopped child has closed its outputs
+                        child.terminate(TIMEOUT_GRACE_PERIOD)?;
+                        return Err(deadline.timeout_error());
+                    }
+
+                    if let Some(progress) = progress.as_mut() {
+                        progress.tick();
+                    }
+                    continue;
+                }
+            },
+            None => match rx.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            },
+        };
+
+        if let Some(progress) = progress.as_mut() {
+            progress.on_line(&line);
+        }
+
+        let line = match on_line(line) {
+            Some(line) => line,
+            None => continue,
+        };
+
+        let output = match line.stream {
+            Stream::Stdout => &mut stdout,
+            Stream::Stderr => &mut stderr,
+        };
+        output.push_str(line.text.as_str());
+        output.push('\n');
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+    join_stdin_writer(writer)?;
+
+    if let Some(progress) = progress.as_mut() {
+        progress.finish();
+    }
+
+    check_output(CommandOutput {
+        stdout,
+        stderr,
+        status: child.wait()?,
+        current_dir,
+        phases: vec![],
+        envs: vec![],
+    })
+}
+
+pub(crate) fn check_output(output: CommandOutput) -> Result<CommandOutput, SimpleError> {
+    if !output.status.success() {
+        return Err(command_error(output.status, output.stderr.as_str()).with_output(output));
+    }
+
+    Ok(output)
+}
+
+pub(crate) fn push_stderr_tail(stderr_tail: &mut VecDeque<String>, line: &str) {
+    if stderr_tail.len() == COMMAND_ERROR_STDERR_MAX_LINES {
+        stderr_tail.pop_front();
+    }
+    stderr_tail.push_back(line.to_string());
+}
+
+/// error of a command which exited with a failure status, with what it wrote on stderr
+pub(crate) fn command_error(status: ExitStatus, stderr: &str) -> SimpleError {
+    let stderr = stderr.trim();
+    SimpleError::new(
+        SimpleErrorKind::Command(status),
+        Some(match stderr.is_empty() {
+            true => "error while executing an internal command".to_string(),
+            false => format!("error while executing an internal command: {}", stderr),
+        }),
+    )
+}
+
+/// interval at which a streamed command is checked for exit or cancellation
+const STREAMING_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// handle on a command started with `exec_streaming`.
+///
+/// Dropping it before `join` cancels the command: the child process is terminated and reaped.
+pub struct StreamingCommand {
+    handle: Option<JoinHandle<Result<ExitStatus, SimpleError>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl StreamingCommand {
+    /// wait for the command to exit and both streams to be fully read, with the same errors as `exec`
+    pub fn join(mut self) -> Result<ExitStatus, SimpleError> {
+        match self.handle.take().unwrap().join() {
+            Ok(result) => result,
+            Err(_) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some("the thread running the command panicked"),
+            )),
+        }
+    }
+
+    /// stop the command, same as dropping the handle
+    pub fn cancel(self) {}
+}
+
+impl Drop for StreamingCommand {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.cancelled.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// execute the command in the background and stream its stdout and stderr lines into a bounded channel,
+/// so a UI can tail the output while the command runs.
+///
+/// At most `capacity` lines are buffered. Once the buffer is full, `backpressure` decides whether
+/// the command output stops being read until the receiver catches up (`Backpressure::Block`, the command
+/// will then block on its own writes) or whether the oldest buffered lines are dropped (`Backpressure::DropOldest`).
+/// Dropping the receiver never blocks the command, the remaining lines are discarded.
+///
+/// Dropping the returned `StreamingCommand` without joining it kills the command.
+/// If the command fails, the last `COMMAND_ERROR_STDERR_MAX_LINES` stderr lines are part of the error
+/// and of its output (`SimpleError::output`, stdout is only streamed and left empty).
+pub fn exec_streaming<P>(
+    binary: P,
+    args: Vec<&str>,
+    capacity: usize,
+    backpressure: Backpressure,
+) -> (StreamingCommand, Receiver<OutputLine>)
+where
+    P: AsRef<Path>,
+{
+    let command_string = command_to_string(binary.as_ref(), &args);
+    let binary_name = binary_name(binary.as_ref());
+    let (send, close, receiver) = bounded_output_channel(capacity, backpressure);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let is_cancelled = cancelled.clone();
+
+    // in dry run mode nothing is spawned, the status of a successful command is returned instead
+    let child = command(binary, args, None, None).and_then(|mut cmd| {
+        log_command(command_string.as_str(), &cmd);
+        match is_dry_run() {
+            true => Ok(Err(dry_run_output(&cmd).status)),
+            false => spawn(&mut cmd).map(|child| Ok((child, current_dir_of(&cmd)))),
+        }
+    });
+
+    let handle = thread::spawn(move || {
+        with_command_metrics(binary_name.as_str(), move || {
+            let (mut child, current_dir) = match child {
+                Ok(Ok((child, current_dir))) => (ChildGuard::new(child), current_dir),
+                Ok(Err(dry_run_status)) => {
+                    info!("dry run, not executed: {}", command_string);
+                    close();
+                    return Ok(dry_run_status);
+                }
+                Err(err) => {
+                    close();
+                    return Err(err);
+                }
+            };
+
+            // the last stderr lines are part of the error if the command fails
+            let stderr_tail = Arc::new(Mutex::new(VecDeque::with_capacity(
+                COMMAND_ERROR_STDERR_MAX_LINES,
+            )));
+            let tail = stderr_tail.clone();
+            let mut send = send;
+            let readers = spawn_output_readers(child.child_mut(), move |line| {
+                if line.stream == Stream::Stderr {
+                    push_stderr_tail(&mut tail.lock().unwrap(), line.text.as_str());
+                }
+                send(line)
+            });
+
+            let exit_status = loop {
+                if is_cancelled.load(Ordering::SeqCst) {
+                    // readers are not joined, they may be blocked on a full channel no one reads anymore
+                    child.terminate(DROP_GRACE_PERIOD)?;
+                    close();
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(format!("command {} has been cancelled", command_string)),
+                    ));
+                }
+
+                if let Some(exit_status) = child.try_wait()? {
+                    break exit_status;
+                }
+
+                thread::sleep(STREAMING_POLL_INTERVAL);
+            };
+
+            for reader in readers {
+                let _ = reader.join();
+            }
+            close();
+
+            if exit_status.success() {
+                return Ok(exit_status);
+            }
+
+            let stderr = Vec::from(stderr_tail.lock().unwrap().clone()).join("\n");
+            Err(
+                command_error(exit_status, stderr.as_str()).with_output(CommandOutput {
+                    stdout: String::new(),
+                    stderr,
+                    status: exit_status,
+                    current_dir,
+                    phases: vec![],
+                    envs: vec![],
+                }),
+            )
+        })
+    });
+
+    (
+        StreamingCommand {
+            handle: Some(handle),
+            cancelled,
+        },
+        receiver,
+    )
 }
 
 // return the output of "binary_name" --version
 pub fn run_version_command_for(binary_name: &str) -> String {
-    let mut output_from_cmd = String::new();
-    exec_with_output(
-        binary_name,
-        vec!["--version"],
-        |r_out| match r_out {
-            Ok(s) => output_from_cmd.push_str(&s.to_owned()),
-            Err(e) => error!("Error while getting stdout from {} {}", binary_name, e),
-        },
-        |r_err| match r_err {
-            Ok(_) => error!("Error executing {}", binary_name),
-            Err(e) => error!("Error while getting stderr from {} {}", binary_name, e),
-        },
-    );
-    output_from_cmd
+    match exec_capture(binary_name, vec!["--version"], Vec::<(&str, &str)>::new()) {
+        Ok(output) => output.stdout,
+        Err(err) => {
+            error!(
+                "Error executing {}: {}",
+                binary_name,
+                err.message.unwrap_or_default()
+            );
+            err.output.map(|output| output.stdout).unwrap_or_default()
+        }
+    }
 }
 
+/// true if the binary is an executable file: a path (containing a separator) is checked as is,
+/// a name is looked up in the `PATH` directories. Nothing is executed.
 pub fn does_binary_exist<S>(binary: S) -> bool
 where
     S: AsRef<OsStr>,
 {
-    match Command::new(binary)
-        .stdout(Stdio::null())
-        .stdin(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-    {
-        Ok(_) => true,
-        _ => false,
+    find_binary(binary.as_ref(), env::var_os("PATH").as_deref()).is_some()
+}
+
+fn find_binary(binary: &OsStr, path: Option<&OsStr>) -> Option<PathBuf> {
+    let binary_path = Path::new(binary);
+    if binary_path.components().count() > 1 {
+        return match is_executable(binary_path) {
+            true => Some(binary_path.to_path_buf()),
+            false => None,
+        };
+    }
+
+    env::split_paths(path?)
+        .map(|directory| directory.join(binary))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+/// make sure the version of the binary, as printed by `binary --version`
+/// (or `binary version --client` for tools like kubectl), is at least `min_version` (ex: "0.13")
+pub fn check_min_version(binary: &str, min_version: &str) -> Result<(), SimpleError> {
+    let min_version = match parse_version(min_version) {
+        Some(min_version) => min_version,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("{} is not a valid version", min_version)),
+            ))
+        }
+    };
+
+    ensure_binary_version(binary, min_version).map(|_| ())
+}
+
+/// output of `binary --version`, or of `binary version --client` for tools like kubectl
+fn version_output(binary: &str) -> Result<CommandOutput, SimpleError> {
+    exec_capture(binary, vec!["--version"], Vec::<(&str, &str)>::new()).or_else(|_| {
+        exec_capture(
+            binary,
+            vec!["version", "--client"],
+            Vec::<(&str, &str)>::new(),
+        )
+    })
+}
+
+/// first semver-looking version (ex: 0.13.4 in "Terraform v0.13.4") found in the output of a version command.
+/// A missing patch number counts as 0 (1.20 == 1.20.0), pre-release and build suffixes are ignored.
+pub fn parse_version(output: &str) -> Option<semver::Version> {
+    let version_regex =
+        VERSION_REGEX.get_or_init(|| Regex::new(r"(\d+)\.(\d+)(?:\.(\d+))?").unwrap());
+    let captures = version_regex.captures(output)?;
+    let number = |i: usize| {
+        captures
+            .get(i)
+            .map_or(Some(0), |x| x.as_str().parse::<u64>().ok())
+    };
+
+    Some(semver::Version::new(number(1)?, number(2)?, number(3)?))
+}
+
+/// make sure the binary is installed with a version at least `min`, and return its version
+pub fn ensure_binary_version(
+    binary: &str,
+    min: semver::Version,
+) -> Result<semver::Version, SimpleError> {
+    let output = version_output(binary)?;
+
+    let version = match parse_version(output.stdout.as_str()) {
+        Some(version) => version,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("unable to find the version of {}", binary)),
+            ))
+        }
+    };
+
+    if version < min {
+        return Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "{} version {} is older than the required {}",
+                binary, version, min
+            )),
+        ));
+    }
+
+    Ok(version)
+}
+
+/// check every required tool (binary name, minimal version) is installed with an acceptable version.
+/// Every tool is checked, and the error lists all the missing or outdated ones.
+pub fn preflight_tools(requirements: &[(&str, &str)]) -> Result<(), SimpleError> {
+    let mut problems = vec![];
+
+    for (binary, min_version) in requirements {
+        if !does_binary_exist(binary) {
+            problems.push(format!("{} is not installed", binary));
+            continue;
+        }
+
+        if let Err(err) = check_min_version(binary, min_version) {
+            problems.push(
+                err.message
+                    .unwrap_or_else(|| format!("unable to check the version of {}", binary)),
+            );
+        }
     }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(SimpleError::new(
+        SimpleErrorKind::Other,
+        Some(format!("missing required tools: {}", problems.join(", "))),
+    ))
 }
 
 pub fn command_to_string<P>(binary: P, args: &Vec<&str>) -> String
 where
     P: AsRef<Path>,
 {
-    format!("{} {}", binary.as_ref().to_str().unwrap(), args.join(" "))
+    format!("{} {}", binary.as_ref().to_string_lossy(), args.join(" "))
 }
 
-pub fn command_with_envs_to_string<P>(
-    binary: P,
-    args: &Vec<&str>,
-    envs: &Vec<(&str, &str)>,
-) -> String
+/// the command line with its envs, for the logs: the values of the sensitive envs are masked
+/// (see `set_sensitive_env_keys`)
+pub fn command_with_envs_to_string<P, K, V>(binary: P, args: &[&str], envs: &[(K, V)]) -> String
 where
     P: AsRef<Path>,
+    K: AsRef<str>,
+    V: AsRef<str>,
 {
-    let _envs = envs
+    let mut parts = envs
         .iter()
-        .map(|(k, v)| format!("{}={}", k, v))
+        .map(|(k, v)| (k.as_ref(), v.as_ref()))
+        .map(|(k, v)| match is_sensitive_env_key(k) {
+            true => format!("{}=***", k),
+            false => format!("{}={}", k, v),
+        })
         .collect::<Vec<_>>();
 
-    format!(
-        "{} {} {}",
-        _envs.join(" "),
-        binary.as_ref().to_str().unwrap(),
-        args.join(" ")
-    )
+    // no separator is left for the envs or the args when there are none
+    parts.push(binary.as_ref().to_string_lossy().to_string());
+    parts.extend(args.iter().map(|x| x.to_string()));
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::command::CommandBuilder;
+    use crate::cmd::output::{CommandProgress, ProgressReporter, Stream};
+    use crate::cmd::utilities::{
+        command_to_string, command_with_envs_to_string, does_binary_exist, exec, exec_capture,
+        exec_combined_output, exec_with_clean_envs, exec_with_envs,
+        exec_with_envs_and_combined_output, exec_with_envs_and_progress, exec_with_output,
+        exec_with_retry, exec_with_stdin, exec_with_timeout, find_binary, is_binary_allowed,
+        matches_sensitive_key, parse_version, set_current_thread_name, DEFAULT_SENSITIVE_ENV_KEYS,
+    };
+    use crate::error::SimpleErrorKind;
+    use std::ffi::{OsStr, OsString};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::PathBuf;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("Terraform v0.13.4\non linux_amd64"),
+            Some(semver::Version::new(0, 13, 4))
+        );
+        assert_eq!(
+            parse_version("Client Version: version.Info{Major:\"1\", GitVersion:\"v1.20\"}"),
+            Some(semver::Version::new(1, 20, 0))
+        );
+        assert_eq!(
+            parse_version("v1.18.3-gke.100"),
+            Some(semver::Version::new(1, 18, 3))
+        );
+        assert_eq!(parse_version("no version"), None);
+
+        let version = |x: &str| parse_version(x).unwrap();
+        assert!(version("0.13.4") >= version("0.13"));
+        assert!(version("1.2") >= version("1.2.0"));
+        assert!(version("0.12.29") < version("0.13"));
+        assert!(version("2.0") >= version("1.19.1"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_set_current_thread_name() {
+        std::thread::spawn(|| {
+            set_current_thread_name("deploy-app-123-with-a-long-name");
+            let name = std::fs::read_to_string("/proc/thread-self/comm").unwrap();
+            assert_eq!(name.trim_end(), "deploy-app-123-");
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_exec_with_output_large_stderr() {
+        // 1MB on stderr is far more than the pipe buffer: if stdout was read before stderr,
+        // the command would block on its stderr write while we wait for stdout to end
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdout = vec![];
+            let mut stderr_bytes = 0;
+            let result = exec_with_output(
+                "sh",
+                vec![
+                    "-c",
+                    "head -c 1048576 /dev/zero | tr '\\0' 'e' >&2; echo done",
+                ],
+                |line| stdout.push(line.unwrap()),
+                |line| stderr_bytes += line.unwrap().len(),
+            );
+            let _ = tx.send((result.is_ok(), stdout, stderr_bytes));
+        });
+
+        let (success, stdout, stderr_bytes) = rx
+            .recv_timeout(Duration::from_secs(60))
+            .expect("the command output is not read concurrently");
+        assert!(success);
+        assert_eq!(stdout, vec!["done"]);
+        assert_eq!(stderr_bytes, 1048576);
+    }
+
+    #[test]
+    fn test_exec_with_owned_envs() {
+        let region = format!("{}1", "fra");
+        let envs = vec![("REGION".to_string(), region)];
+        assert!(exec_with_envs("sh", vec!["-c", r#"test "$REGION" = fra1"#], envs).is_ok());
+
+        let envs = vec![("TOKEN".to_string(), "secret".to_string())];
+        assert_eq!(
+            command_with_envs_to_string("terraform", &["apply"], &envs),
+            "TOKEN=*** terraform apply"
+        );
+    }
+
+    #[test]
+    fn test_exec_with_envs_and_progress() {
+        let mut reports = vec![];
+        exec_with_envs_and_progress(
+            "sh",
+            vec!["-c", "echo a; echo bb; echo c >&2; echo d; echo $LAST"],
+            vec![("LAST", "e")],
+            |_| {},
+            |_| {},
+            ProgressReporter::new(2, Duration::from_secs(0), |x| reports.push(x)),
+        )
+        .unwrap();
+
+        // every 2 lines, then once the output is read
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].stdout_lines + reports[0].stderr_lines, 2);
+        let last = reports.last().unwrap();
+        assert_eq!((last.stdout_lines, last.stderr_lines), (4, 1));
+        assert_eq!((last.stdout_bytes, last.stderr_bytes), (5, 1));
+
+        // a silent command is reported too
+        let mut reports = 0;
+        exec_with_envs_and_progress(
+            "sleep",
+            vec!["1"],
+            Vec::<(&str, &str)>::new(),
+            |_| {},
+            |_| {},
+            ProgressReporter::new(0, Duration::from_millis(200), |_| reports += 1),
+        )
+        .unwrap();
+        assert!(reports >= 4);
+
+        let progress = Arc::new(Mutex::new(CommandProgress::default()));
+        let reported = progress.clone();
+        CommandBuilder::new("sh")
+            .args(vec!["-c", "echo a; echo b >&2"])
+            .on_progress(ProgressReporter::new(0, Duration::from_secs(0), move |x| {
+                *reported.lock().unwrap() = x
+            }))
+            .run()
+            .unwrap();
+        assert_eq!(progress.lock().unwrap().stdout_lines, 1);
+        assert_eq!(progress.lock().unwrap().stderr_lines, 1);
+    }
+
+    #[test]
+    fn test_exec_with_clean_envs() {
+        // cargo sets it on the test process, it must not reach the command
+        assert!(std::env::var_os("CARGO_PKG_NAME").is_some());
+
+        let script = r#"test -z "$CARGO_PKG_NAME" && test -n "$PATH" && test "$FOO" = bar"#;
+        assert!(exec_with_clean_envs("sh", vec!["-c", script], vec![("FOO", "bar")]).is_ok());
+
+        // the kept envs can be overridden
+        let script = r#"test "$HOME" = /nowhere"#;
+        assert!(exec_with_clean_envs("sh", vec!["-c", script], vec![("HOME", "/nowhere")]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_with_output_kills_the_child_on_panic() {
+        let mut pid = None;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            exec_with_output(
+                "sh",
+                vec!["-c", "echo $$; exec sleep 30"],
+                |line| {
+                    pid = line.unwrap().parse::<libc::pid_t>().ok();
+                    panic!("the stdout callback panics");
+                },
+                |_| {},
+            )
+        }));
+        assert!(result.is_err());
+
+        // the process does not exist anymore, not even as a zombie
+        assert_eq!(unsafe { libc::kill(pid.unwrap(), 0) }, -1);
+    }
+
+    #[test]
+    fn test_exec_with_timeout() {
+        let mut stdout = vec![];
+        let started_at = Instant::now();
+        let err = exec_with_timeout(
+            "sh",
+            vec!["-c", "echo started; sleep 30"],
+            Vec::<(&str, &str)>::new(),
+            Duration::from_millis(500),
+            |line| stdout.push(line.unwrap()),
+            |_| {},
+        )
+        .unwrap_err();
+
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        assert!(matches!(err.kind, SimpleErrorKind::Timeout(_)));
+        assert!(err.message.unwrap().contains("sleep 30"));
+        assert_eq!(stdout, vec!["started"]);
+
+        assert!(exec_with_timeout(
+            "sh",
+            vec!["-c", "echo done"],
+            Vec::<(&str, &str)>::new(),
+            Duration::from_secs(10),
+            |_| {},
+            |_| {},
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_command_error_has_stderr() {
+        let err = exec(
+            "sh",
+            vec![
+                "-c",
+                "echo progress; echo 'Error: invalid manifest' >&2; exit 1",
+            ],
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(1)));
+        assert_eq!(
+            err.message.unwrap(),
+            "error while executing an internal command: Error: invalid manifest"
+        );
+
+        let mut stderr = vec![];
+        let err = exec_with_output(
+            "sh",
+            vec!["-c", "echo 'Error: invalid manifest' >&2; exit 1"],
+            |_| {},
+            |line| stderr.push(line.unwrap()),
+        )
+        .unwrap_err();
+        assert_eq!(stderr, vec!["Error: invalid manifest"]);
+        assert_eq!(
+            err.message.unwrap(),
+            "error while executing an internal command: Error: invalid manifest"
+        );
+    }
+
+    #[test]
+    fn test_exec_with_retry() {
+        let attempts_file =
+            std::env::temp_dir().join(format!("exec-with-retry-{}", std::process::id()));
+        let attempts_file = attempts_file.to_str().unwrap();
+
+        // fails on the first attempt only
+        let script = format!(
+            "echo attempt >> {0}; [ $(wc -l < {0}) -ge 2 ]",
+            attempts_file
+        );
+        exec_with_retry(
+            "sh",
+            vec!["-c", script.as_str()],
+            Vec::<(&str, &str)>::new(),
+            3,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(attempts_file)
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        std::fs::remove_file(attempts_file).unwrap();
+
+        let err = exec_with_retry(
+            "sh",
+            vec!["-c", "echo denied >&2; exit 3"],
+            Vec::<(&str, &str)>::new(),
+            1,
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(3)));
+        assert_eq!(
+            err.message.unwrap(),
+            "error while executing an internal command: denied"
+        );
+    }
+
+    #[test]
+    fn test_is_binary_allowed() {
+        let allowed = ["terraform", "/usr/local/bin/kubectl"];
+
+        assert!(is_binary_allowed("terraform", &allowed));
+        assert!(is_binary_allowed("/usr/local/bin/kubectl", &allowed));
+        assert!(!is_binary_allowed("/tmp/evil/terraform", &allowed));
+        assert!(!is_binary_allowed("./terraform", &allowed));
+        assert!(!is_binary_allowed("kubectl", &allowed));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_binary() {
+        let path = OsStr::new("/nonexistent:/bin:/usr/bin");
+
+        assert!(find_binary(OsStr::new("sh"), Some(path)).is_some());
+        assert!(find_binary(OsStr::new("qovery-missing-binary"), Some(path)).is_none());
+        assert!(find_binary(OsStr::new("sh"), None).is_none());
+        assert_eq!(
+            find_binary(OsStr::new("/bin/sh"), None),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        // a file which is not executable
+        let not_executable =
+            std::env::temp_dir().join(format!("not-executable-{}", std::process::id()));
+        std::fs::write(&not_executable, "#!/bin/sh").unwrap();
+        assert!(find_binary(not_executable.as_os_str(), Some(path)).is_none());
+        std::fs::remove_file(&not_executable).unwrap();
+        assert!(does_binary_exist("sh"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_non_utf8_binary_path() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let binary = PathBuf::from(OsString::from_vec(b"/tmp/qovery-\xff-binary".to_vec()));
+
+        assert_eq!(
+            command_to_string(&binary, &vec!["version"]),
+            "/tmp/qovery-\u{fffd}-binary version"
+        );
+        assert_eq!(
+            command_with_envs_to_string(&binary, &["version"], &[("KEY", "value")]),
+            "KEY=*** /tmp/qovery-\u{fffd}-binary version"
+        );
+        // the binary is not there, but the path does not make the engine panic
+        let err = exec(&binary, vec!["version"]).unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::SpawnFailed { .. }));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_binary_path_with_whitespace() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("qovery bin dir {}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("print-dir.sh");
+        fs::write(&script, "#!/bin/sh\npwd\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // the whole path is the binary, it is not split into a current directory and a binary
+        let output = exec_capture(&script, vec![], Vec::<(&str, &str)>::new()).unwrap();
+        assert_eq!(
+            PathBuf::from(output.stdout.trim()),
+            std::env::current_dir().unwrap()
+        );
+        assert!(exec(format!("{} sh", dir.display()), vec!["-c", "true"]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_exec_capture_error_has_output() {
+        let err = exec_capture(
+            "sh",
+            vec!["-c", "echo partial plan; echo locked >&2; exit 1"],
+            Vec::<(&str, &str)>::new(),
+        )
+        .unwrap_err();
+
+        let output = err.output.unwrap();
+        assert_eq!(output.stdout, "partial plan\n");
+        assert_eq!(output.stderr, "locked\n");
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn test_exec_with_stdin() {
+        let output = exec_with_stdin(
+            "sh",
+            vec!["-c", "wc -l; cat >&2"],
+            Vec::<(&str, &str)>::new(),
+            b"apiVersion: v1\nkind: Namespace\n",
+        )
+        .unwrap();
+        assert_eq!(output.stdout.trim(), "2");
+        assert_eq!(output.stderr, "");
+
+        // more input than the pipe buffer, and the command does not read it
+        let input = vec![b'x'; 1024 * 1024];
+        let err = exec_with_stdin(
+            "sh",
+            vec!["-c", "exit 3"],
+            Vec::<(&str, &str)>::new(),
+            &input,
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, SimpleErrorKind::Command(status) if status.code() == Some(3)));
+    }
+
+    #[test]
+    fn test_sensitive_env_keys() {
+        assert!(matches_sensitive_key(
+            "spaces_secret_key",
+            DEFAULT_SENSITIVE_ENV_KEYS
+        ));
+        assert!(matches_sensitive_key(
+            "DIGITAL_OCEAN_TOKEN",
+            DEFAULT_SENSITIVE_ENV_KEYS
+        ));
+        assert!(!matches_sensitive_key(
+            "KUBECONFIG",
+            DEFAULT_SENSITIVE_ENV_KEYS
+        ));
+        assert!(matches_sensitive_key("KUBECONFIG", &["kubeconfig"]));
+
+        assert_eq!(
+            command_with_envs_to_string(
+                "terraform",
+                &["apply"],
+                &[("AWS_SECRET_ACCESS_KEY", "abcd"), ("TF_LOG", "INFO")]
+            ),
+            "AWS_SECRET_ACCESS_KEY=*** TF_LOG=INFO terraform apply"
+        );
+        assert_eq!(
+            command_with_envs_to_string("terraform", &["apply"], &Vec::<(&str, &str)>::new()),
+            "terraform apply"
+        );
+        assert_eq!(
+            command_with_envs_to_string("terraform", &[], &[("TF_LOG", "INFO")]),
+            "TF_LOG=INFO terraform"
+        );
+    }
+
+    #[test]
+    fn test_spawn_failed() {
+        let err = exec("qovery-missing-binary", vec!["version"]).unwrap_err();
+        assert!(
+            matches!(err.kind, SimpleErrorKind::SpawnFailed { binary } if binary == "qovery-missing-binary")
+        );
+    }
+
+    #[test]
+    fn test_exec_combined_output() {
+        let (output, status) = exec_combined_output(
+            "sh",
+            vec![
+                "-c",
+                "echo out; sleep 0.2; echo err >&2; sleep 0.2; echo out; exit 3",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(output, "out\nerr\nout\n");
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_exec_with_envs_and_combined_output() {
+        let mut lines = vec![];
+        let result = exec_with_envs_and_combined_output(
+            "sh",
+            vec![
+                "-c",
+                "echo $FIRST; sleep 0.2; echo err >&2; sleep 0.2; echo last; exit 3",
+            ],
+            vec![("FIRST", "first")],
+            |line| lines.push(line),
+        );
+
+        assert!(matches!(
+            result.unwrap_err().kind,
+            SimpleErrorKind::Command(_)
+        ));
+        assert_eq!(
+            lines
+                .iter()
+                .map(|x| (x.stream, x.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Stream::Stdout, "first"),
+                (Stream::Stderr, "err"),
+                (Stream::Stdout, "last"),
+            ]
+        );
+        assert!(lines[0].at < lines[1].at && lines[1].at < lines[2].at);
+    }
+
+    #[test]
+    fn test_serialize_redacted_output() {
+        let output = exec_capture(
+            "sh",
+            vec!["-c", "echo token=my-token"],
+            Vec::<(&str, &str)>::new(),
+        )
+        .unwrap();
+        let json = serde_json::to_value(output.redacted(&["my-token"])).unwrap();
+
+        assert_eq!(json["stdout"], "token=****\n");
+        assert_eq!(json["status"], 0);
+    }
 }
@@ -2,4 +2,8 @@ pub const TF_PLUGIN_CACHE_DIR: &str = "TF_PLUGIN_CACHE_DIR";
 pub const AWS_ACCESS_KEY_ID: &str = "AWS_ACCESS_KEY_ID";
 pub const AWS_SECRET_ACCESS_KEY: &str = "AWS_SECRET_ACCESS_KEY";
 pub const KUBECONFIG: &str = "KUBECONFIG";
-pub const DIGITAL_OCEAN_TOKEN: &str = "DIGITAL_OCEAN_TOKEN";
\ No newline at end of file
+pub const DIGITAL_OCEAN_TOKEN: &str = "DIGITAL_OCEAN_TOKEN";
+/// env var set on the spawned commands with the engine task they are run for
+pub const ENGINE_TASK: &str = "ENGINE_TASK";
+/// User-Agent sent to the cloud provider APIs, so the engine traffic can be identified in their audit logs
+pub const USER_AGENT: &str = concat!("qovery-engine/", env!("CARGO_PKG_VERSION"));
@@ -200,10 +200,10 @@ impl DOCR {
 pub fn get_header_with_bearer(token: &str) -> HeaderMap<HeaderValue> {
     let mut headers = header::HeaderMap::new();
     headers.insert("Content-Type", "application/json".parse().unwrap());
-    headers.insert(
-        "Authorization",
-        format!("Bearer {}", token).parse().unwrap(),
-    );
+    let mut authorization: HeaderValue = format!("Bearer {}", token).parse().unwrap();
+    // shown as "Sensitive" instead of the token when the headers are logged
+    authorization.set_sensitive(true);
+    headers.insert("Authorization", authorization);
     headers
 }
 
@@ -254,6 +254,8 @@ impl ContainerRegistry for DOCR {
 
     // https://www.digitalocean.com/docs/images/container-registry/how-to/use-registry-docker-kubernetes/
     fn push(&self, image: &Image, _force_push: bool) -> Result<PushResult, EngineError> {
+        self.check_docker_available()?;
+
         let image = image.clone();
         match self.create_repository(&image) {
             Ok(_) => info!(
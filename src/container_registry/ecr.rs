@@ -290,6 +290,8 @@ impl ContainerRegistry for ECR {
     }
 
     fn push(&self, image: &Image, force_push: bool) -> Result<PushResult, EngineError> {
+        self.check_docker_available()?;
+
         let r = async_run(
             self.ecr_client()
                 .get_authorization_token(GetAuthorizationTokenRequest::default()),
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use crate::build_platform::Image;
+use crate::cmd::docker::docker_available_with_envs;
 use crate::error::{EngineError, EngineErrorCause, EngineErrorScope};
 use crate::models::{Context, Listener};
 
@@ -25,6 +26,20 @@ pub trait ContainerRegistry {
     fn does_image_exists(&self, image: &Image) -> bool;
     fn push(&self, image: &Image, force_push: bool) -> Result<PushResult, EngineError>;
     fn push_error(&self, image: &Image) -> Result<PushResult, EngineError>;
+    /// preflight check before the docker commands of a push: fails if the Docker daemon is not reachable
+    fn check_docker_available(&self) -> Result<(), EngineError> {
+        let envs = match self.context().docker_tcp_socket() {
+            Some(tcp_socket) => vec![("DOCKER_HOST", tcp_socket.as_str())],
+            None => vec![],
+        };
+
+        docker_available_with_envs(envs).map_err(|err| {
+            self.engine_error(
+                EngineErrorCause::Internal,
+                err.message.unwrap_or_else(|| "the Docker daemon is not available".to_string()),
+            )
+        })
+    }
     fn engine_error_scope(&self) -> EngineErrorScope {
         EngineErrorScope::ContainerRegistry(self.id().to_string(), self.name().to_string())
     }
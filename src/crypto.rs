@@ -1,4 +1,10 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
 use crypto::digest::Digest;
+use crypto::md5::Md5;
 use crypto::sha1::Sha1;
 
 pub fn to_sha1(input: &str) -> String {
@@ -12,3 +18,19 @@ pub fn to_sha1_truncate_16(input: &str) -> String {
     hash_str.truncate(16);
     hash_str
 }
+
+/// hex md5 of the file content, read by chunks
+pub fn file_md5(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.input(&buffer[..read]),
+        }
+    }
+
+    Ok(hasher.result_str())
+}
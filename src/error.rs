@@ -1,4 +1,9 @@
+use std::error::Error;
+use std::fmt;
 use std::process::ExitStatus;
+use std::time::Duration;
+
+use crate::cmd::utilities::CommandOutput;
 
 pub type Type = String;
 pub type Id = String;
@@ -60,6 +65,10 @@ pub enum EngineErrorCause {
 pub struct SimpleError {
     pub kind: SimpleErrorKind,
     pub message: Option<String>,
+    /// what the failed command wrote (stdout, stderr, exit status), when it has been captured
+    pub output: Option<Box<CommandOutput>>,
+    /// the underlying error, if any (ex: an `io::Error`)
+    pub source: Option<Box<dyn Error + Send + Sync>>,
 }
 
 #[derive(Debug)]
@@ -70,6 +79,19 @@ pub struct StringError {
 #[derive(Debug)]
 pub enum SimpleErrorKind {
     Command(ExitStatus),
+    Timeout(Duration),
+    /// the requested resource does not exist (yet)
+    NotFound,
+    /// the object storage bucket does not exist
+    BucketNotFound,
+    /// the terraform state is locked by another run, `lock_id` can be used to force-unlock it
+    StateLocked { lock_id: Option<String> },
+    /// the credentials are rejected (invalid or expired), retrying with the same ones is pointless
+    Unauthorized,
+    /// the command could not be started, the binary is likely not installed
+    SpawnFailed { binary: String },
+    /// the request could not be sent (DNS resolution, connection or TLS failure)
+    Connection,
     Other,
 }
 
@@ -81,10 +103,92 @@ impl SimpleError {
                 Some(message) => Some(message.into()),
                 _ => None,
             },
+            output: None,
+            source: None,
+        }
+    }
+
+    pub fn with_output(mut self, output: CommandOutput) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn with_source<E: Error + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// exit status of the failed command: the one of `SimpleErrorKind::Command`,
+    /// or the one of the captured output for the other kinds (ex: `StateLocked`)
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        match &self.kind {
+            SimpleErrorKind::Command(exit_status) => Some(*exit_status),
+            _ => self.output.as_ref().map(|output| output.status),
+        }
+    }
+
+    /// exit code of the failed command (ex: `Some(2)` for terraform plan changes),
+    /// `None` if it has been killed by a signal or the error is not about a command
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_status().and_then(|exit_status| exit_status.code())
+    }
+
+    /// number of the signal which killed the command (ex: `Some(9)` for SIGKILL)
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+
+        self.exit_status().and_then(|exit_status| exit_status.signal())
+    }
+
+    /// processes are not killed by signals on non-unix platforms
+    #[cfg(not(unix))]
+    pub fn signal(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl fmt::Display for SimpleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimpleErrorKind::Command(exit_status) => write!(f, "command failed ({})", exit_status),
+            SimpleErrorKind::Timeout(duration) if duration.as_secs() == 0 => write!(f, "timed out"),
+            SimpleErrorKind::Timeout(duration) => {
+                write!(f, "timed out after {} seconds", duration.as_secs())
+            }
+            SimpleErrorKind::NotFound => write!(f, "not found"),
+            SimpleErrorKind::BucketNotFound => write!(f, "bucket not found"),
+            SimpleErrorKind::StateLocked { lock_id: Some(lock_id) } => {
+                write!(f, "state locked (lock id {})", lock_id)
+            }
+            SimpleErrorKind::StateLocked { lock_id: None } => write!(f, "state locked"),
+            SimpleErrorKind::Unauthorized => write!(f, "unauthorized"),
+            SimpleErrorKind::SpawnFailed { binary } => {
+                write!(f, "unable to start {} (is it installed?)", binary)
+            }
+            SimpleErrorKind::Connection => write!(f, "connection failed"),
+            SimpleErrorKind::Other => write!(f, "error"),
+        }
+    }
+}
+
+/// "<kind>: <message>", or only the message for `SimpleErrorKind::Other`
+impl fmt::Display for SimpleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.kind, &self.message) {
+            (SimpleErrorKind::Other, Some(message)) => write!(f, "{}", message),
+            (kind, Some(message)) => write!(f, "{}: {}", kind, message),
+            (kind, None) => write!(f, "{}", kind),
         }
     }
 }
 
+impl Error for SimpleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
+}
+
 impl StringError {
     pub fn new(message: String) -> Self {
         StringError { message }
@@ -93,7 +197,22 @@ impl StringError {
 
 impl From<std::io::Error> for SimpleError {
     fn from(err: std::io::Error) -> Self {
-        SimpleError::new(SimpleErrorKind::Other, Some(err.to_string()))
+        SimpleError::new(SimpleErrorKind::Other, Some(err.to_string())).with_source(err)
+    }
+}
+
+/// the timeout duration is not known from a reqwest error, it is left to zero
+impl From<reqwest::Error> for SimpleError {
+    fn from(err: reqwest::Error) -> Self {
+        let kind = if err.is_timeout() {
+            SimpleErrorKind::Timeout(Duration::default())
+        } else if err.is_request() {
+            SimpleErrorKind::Connection
+        } else {
+            SimpleErrorKind::Other
+        };
+
+        SimpleError::new(kind, Some(err.to_string())).with_source(err)
     }
 }
 
@@ -116,7 +235,31 @@ pub fn cast_simple_error_to_engine_error<X, T: Into<String>>(
                     simple_error.message.unwrap_or("<no message>".into()),
                     exit_status
                 ),
-                SimpleErrorKind::Other => simple_error.message.unwrap_or("<no message>".into()),
+                // the duration is unknown for a timeout of an HTTP request (see `From<reqwest::Error>`)
+                SimpleErrorKind::Timeout(duration) if duration.as_secs() == 0 => format!(
+                    "{} (timed out)",
+                    simple_error.message.unwrap_or("<no message>".into())
+                ),
+                SimpleErrorKind::Timeout(duration) => format!(
+                    "{} (timed out after {}s)",
+                    simple_error.message.unwrap_or("<no message>".into()),
+                    duration.as_secs()
+                ),
+                SimpleErrorKind::StateLocked { lock_id } => format!(
+                    "{} (terraform state locked by {})",
+                    simple_error.message.unwrap_or("<no message>".into()),
+                    lock_id.unwrap_or("an unknown lock".into())
+                ),
+                SimpleErrorKind::Unauthorized => format!(
+                    "{} (invalid or expired credentials)",
+                    simple_error.message.unwrap_or("<no message>".into())
+                ),
+                SimpleErrorKind::SpawnFailed { binary } => format!(
+                    "{} (is {} installed?)",
+                    simple_error.message.unwrap_or("<no message>".into()),
+                    binary
+                ),
+                _ => simple_error.message.unwrap_or("<no message>".into()),
             };
 
             Err(EngineError::new(
@@ -129,3 +272,90 @@ pub fn cast_simple_error_to_engine_error<X, T: Into<String>>(
         Ok(x) => Ok(x),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{
+        cast_simple_error_to_engine_error, EngineErrorScope, SimpleError, SimpleErrorKind,
+    };
+    use std::error::Error;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_simple_error_display_and_source() {
+        let err = SimpleError::new(SimpleErrorKind::NotFound, Some("no cluster named prod"));
+        assert_eq!(err.to_string(), "not found: no cluster named prod");
+        assert!(err.source().is_none());
+
+        let err = SimpleError::new(SimpleErrorKind::Other, Some("something went wrong"));
+        assert_eq!(err.to_string(), "something went wrong");
+
+        let err = SimpleError::new::<String>(SimpleErrorKind::Unauthorized, None);
+        assert_eq!(err.to_string(), "unauthorized");
+
+        let err = SimpleError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(err.to_string(), "no such file");
+        assert_eq!(err.source().unwrap().to_string(), "no such file");
+
+        let boxed: Box<dyn Error> = Box::new(err);
+        assert_eq!(boxed.to_string(), "no such file");
+    }
+
+    #[test]
+    fn test_from_reqwest_error() {
+        // nothing listens on the port 1
+        let err = reqwest::blocking::get("http://127.0.0.1:1").unwrap_err();
+        let err = SimpleError::from(err);
+
+        assert!(matches!(err.kind, SimpleErrorKind::Connection));
+        assert!(err.to_string().starts_with("connection failed: "));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_cast_timeout_to_engine_error() {
+        let message = |duration: Duration| {
+            let err = SimpleError::new(SimpleErrorKind::Timeout(duration), Some("request"));
+            cast_simple_error_to_engine_error::<(), _>(EngineErrorScope::Engine, "exec", Err(err))
+                .unwrap_err()
+                .message
+                .unwrap()
+        };
+
+        assert_eq!(message(Duration::from_secs(30)), "request (timed out after 30s)");
+        assert_eq!(message(Duration::default()), "request (timed out)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code_and_signal() {
+        use crate::cmd::runner::MockRunner;
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let command_error = |raw_status| {
+            SimpleError::new(
+                SimpleErrorKind::Command(ExitStatus::from_raw(raw_status)),
+                None::<&str>,
+            )
+        };
+
+        let err = command_error(2 << 8);
+        assert_eq!(err.exit_code(), Some(2));
+        assert_eq!(err.signal(), None);
+
+        // killed by SIGKILL
+        let err = command_error(9);
+        assert_eq!(err.exit_code(), None);
+        assert_eq!(err.signal(), Some(9));
+
+        let err = SimpleError::new(SimpleErrorKind::StateLocked { lock_id: None }, None::<&str>)
+            .with_output(MockRunner::output("", "Error acquiring the state lock", 1));
+        assert_eq!(err.exit_code(), Some(1));
+
+        let err = SimpleError::new(SimpleErrorKind::NotFound, None::<&str>);
+        assert_eq!(err.exit_code(), None);
+        assert_eq!(err.signal(), None);
+    }
+}
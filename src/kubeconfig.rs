@@ -0,0 +1,433 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{read_to_string, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::KUBECONFIG;
+use crate::error::{SimpleError, SimpleErrorKind};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Kubeconfig {
+    #[serde(rename = "apiVersion")]
+    pub api_version: Option<String>,
+    #[serde(default)]
+    pub clusters: Vec<KubeconfigCluster>,
+    #[serde(default)]
+    pub contexts: Vec<KubeconfigContext>,
+    #[serde(default)]
+    pub users: Vec<KubeconfigUser>,
+    #[serde(rename = "current-context")]
+    pub current_context: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KubeconfigCluster {
+    pub name: String,
+    pub cluster: serde_yaml::Value,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KubeconfigContext {
+    pub name: String,
+    pub context: KubeconfigContextDetails,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KubeconfigContextDetails {
+    pub cluster: String,
+    pub user: String,
+    pub namespace: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KubeconfigUser {
+    pub name: String,
+    pub user: serde_yaml::Value,
+}
+
+pub fn parse_kubeconfig(content: &str) -> Result<Kubeconfig, SimpleError> {
+    match serde_yaml::from_str::<Kubeconfig>(content) {
+        Ok(kubeconfig) => Ok(kubeconfig),
+        Err(err) => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("unable to parse the kubeconfig: {}", err)),
+        )),
+    }
+}
+
+/// parse the kubeconfig and make sure it has an `apiVersion`, clusters and contexts,
+/// to catch a truncated download or an error page before kubectl fails on it
+pub fn validate_kubeconfig(content: &str) -> Result<Kubeconfig, SimpleError> {
+    let kubeconfig = parse_kubeconfig(content)?;
+
+    let missing = match &kubeconfig {
+        x if x.api_version.is_none() => Some("apiVersion"),
+        x if x.clusters.is_empty() => Some("clusters"),
+        x if x.contexts.is_empty() => Some("contexts"),
+        _ => None,
+    };
+
+    match missing {
+        Some(key) => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("invalid kubeconfig: no {}", key)),
+        )),
+        None => Ok(kubeconfig),
+    }
+}
+
+pub fn read_kubeconfig(path: &Path) -> Result<Kubeconfig, SimpleError> {
+    parse_kubeconfig(read_to_string(path)?.as_str())
+}
+
+/// path of the ambient kubeconfig, for when no kubeconfig is downloaded for the cluster:
+/// the first path of the `KUBECONFIG` env var if it is set, `~/.kube/config` otherwise.
+/// `None` if `KUBECONFIG` is not set and there is no home directory.
+pub fn default_kubeconfig_path() -> Option<PathBuf> {
+    kubeconfig_path(env::var_os(KUBECONFIG).as_deref(), dirs::home_dir())
+}
+
+fn kubeconfig_path(kubeconfig_env: Option<&OsStr>, home_dir: Option<PathBuf>) -> Option<PathBuf> {
+    // like kubectl, KUBECONFIG may hold several paths separated by ':' (';' on Windows)
+    let from_env = kubeconfig_env
+        .and_then(|paths| env::split_paths(paths).find(|path| !path.as_os_str().is_empty()));
+
+    from_env.or_else(|| home_dir.map(|home_dir| home_dir.join(".kube").join("config")))
+}
+
+/// return the API server url (`cluster.server`) of the given context,
+/// or of the current context if none is given
+pub fn kubeconfig_server_url(path: &Path, context: Option<&str>) -> Result<String, SimpleError> {
+    let kubeconfig = read_kubeconfig(path)?;
+    server_url(&kubeconfig, context)
+}
+
+fn server_url(kubeconfig: &Kubeconfig, context: Option<&str>) -> Result<String, SimpleError> {
+    let context_name = match context.or(kubeconfig.current_context.as_deref()) {
+        Some(context_name) => context_name,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some("no context given and no current-context set in the kubeconfig"),
+            ))
+        }
+    };
+
+    let context = match kubeconfig.contexts.iter().find(|x| x.name == context_name) {
+        Some(context) => context,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "context {} not found in the kubeconfig",
+                    context_name
+                )),
+            ))
+        }
+    };
+
+    let cluster_name = context.context.cluster.as_str();
+    let cluster = match kubeconfig.clusters.iter().find(|x| x.name == cluster_name) {
+        Some(cluster) => cluster,
+        None => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "cluster {} of context {} not found in the kubeconfig",
+                    cluster_name, context_name
+                )),
+            ))
+        }
+    };
+
+    match cluster.cluster.get("server").and_then(|x| x.as_str()) {
+        Some(server) => Ok(server.to_string()),
+        None => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!(
+                "cluster {} has no server field in the kubeconfig",
+                cluster_name
+            )),
+        )),
+    }
+}
+
+/// overwrite the kubeconfig with zeros before removing it, so the cluster credentials it contains
+/// are not left on disk. This is best-effort: it does not defeat journaling or copy-on-write filesystems.
+/// An already removed kubeconfig is not an error.
+pub fn cleanup_kubeconfig(path: &Path) -> Result<(), SimpleError> {
+    let mut file = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(SimpleError::from(err)),
+    };
+
+    let zeros = [0u8; 4096];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let size = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..size])?;
+        remaining -= size as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(SimpleError::from(err)),
+        _ => Ok(()),
+    }
+}
+
+/// cleans up the kubeconfig (see `cleanup_kubeconfig`) when dropped
+/// ```no_run
+/// use qovery_engine::kubeconfig::KubeconfigGuard;
+///
+/// let kubeconfig = KubeconfigGuard::new("/tmp/kubernetes_config_my-cluster");
+/// // use kubeconfig.path(), the file is removed at the end of the scope
+/// ```
+pub struct KubeconfigGuard {
+    path: PathBuf,
+}
+
+impl KubeconfigGuard {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        KubeconfigGuard {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+impl Drop for KubeconfigGuard {
+    fn drop(&mut self) {
+        if let Err(err) = cleanup_kubeconfig(self.path.as_path()) {
+            error!(
+                "unable to clean up the kubeconfig {}: {:?}",
+                self.path.display(),
+                err.message
+            );
+        }
+    }
+}
+
+/// what changed between two kubeconfigs, entries are compared by name
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KubeconfigDiff {
+    pub clusters: NamedEntriesDiff,
+    pub users: NamedEntriesDiff,
+    pub contexts: NamedEntriesDiff,
+}
+
+impl KubeconfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty() && self.users.is_empty() && self.contexts.is_empty()
+    }
+}
+
+/// names of the entries only in the second kubeconfig (added), only in the first one (removed),
+/// or in both but with a different content (changed)
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamedEntriesDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl NamedEntriesDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// report the clusters, users and contexts added, removed or changed from kubeconfig `a` to kubeconfig `b`
+/// (ex: a re-downloaded kubeconfig with another server or rotated certificates means the cluster has been recreated)
+pub fn diff_kubeconfigs(a: &Path, b: &Path) -> Result<KubeconfigDiff, SimpleError> {
+    Ok(diff(&read_kubeconfig(a)?, &read_kubeconfig(b)?))
+}
+
+fn diff(a: &Kubeconfig, b: &Kubeconfig) -> KubeconfigDiff {
+    KubeconfigDiff {
+        clusters: diff_entries(
+            a.clusters.iter().map(|x| (x.name.as_str(), &x.cluster)),
+            b.clusters.iter().map(|x| (x.name.as_str(), &x.cluster)),
+        ),
+        users: diff_entries(
+            a.users.iter().map(|x| (x.name.as_str(), &x.user)),
+            b.users.iter().map(|x| (x.name.as_str(), &x.user)),
+        ),
+        contexts: diff_entries(
+            a.contexts.iter().map(|x| (x.name.as_str(), &x.context)),
+            b.contexts.iter().map(|x| (x.name.as_str(), &x.context)),
+        ),
+    }
+}
+
+fn diff_entries<'a, T, A, B>(a: A, b: B) -> NamedEntriesDiff
+where
+    T: PartialEq + 'a,
+    A: Iterator<Item = (&'a str, &'a T)>,
+    B: Iterator<Item = (&'a str, &'a T)>,
+{
+    let a = a.collect::<Vec<_>>();
+    let b = b.collect::<Vec<_>>();
+    let mut diff = NamedEntriesDiff::default();
+
+    for (name, a_value) in &a {
+        match b.iter().find(|(b_name, _)| b_name == name) {
+            Some((_, b_value)) if a_value != b_value => diff.changed.push(name.to_string()),
+            Some(_) => {}
+            None => diff.removed.push(name.to_string()),
+        }
+    }
+
+    for (name, _) in &b {
+        if !a.iter().any(|(a_name, _)| a_name == name) {
+            diff.added.push(name.to_string());
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kubeconfig::{
+        cleanup_kubeconfig, diff, kubeconfig_path, parse_kubeconfig, server_url,
+        validate_kubeconfig, KubeconfigGuard, NamedEntriesDiff,
+    };
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    const KUBECONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+current-context: do-fra1-cluster
+clusters:
+- name: do-fra1-cluster
+  cluster:
+    certificate-authority-data: Y2VydGlmaWNhdGU=
+    server: https://9a1b2c3d.k8s.ondigitalocean.com
+- name: other-cluster
+  cluster:
+    certificate-authority-data: Y2VydGlmaWNhdGU=
+contexts:
+- name: do-fra1-cluster
+  context:
+    cluster: do-fra1-cluster
+    user: do-fra1-cluster-admin
+- name: other
+  context:
+    cluster: other-cluster
+    user: do-fra1-cluster-admin
+users:
+- name: do-fra1-cluster-admin
+  user:
+    token: my-token
+"#;
+
+    #[test]
+    fn test_validate_kubeconfig() {
+        assert!(validate_kubeconfig(KUBECONFIG).is_ok());
+
+        assert!(validate_kubeconfig("<html><body>503 Service Unavailable</body></html>").is_err());
+        assert!(validate_kubeconfig("").is_err());
+        let err =
+            validate_kubeconfig(KUBECONFIG.replace("apiVersion: v1\n", "").as_str()).unwrap_err();
+        assert_eq!(err.message.unwrap(), "invalid kubeconfig: no apiVersion");
+        let err = validate_kubeconfig("apiVersion: v1\nclusters: []\n").unwrap_err();
+        assert_eq!(err.message.unwrap(), "invalid kubeconfig: no clusters");
+    }
+
+    #[test]
+    fn test_server_url() {
+        let kubeconfig = parse_kubeconfig(KUBECONFIG).unwrap();
+
+        assert_eq!(
+            server_url(&kubeconfig, None).unwrap(),
+            "https://9a1b2c3d.k8s.ondigitalocean.com"
+        );
+        assert_eq!(
+            server_url(&kubeconfig, Some("do-fra1-cluster")).unwrap(),
+            "https://9a1b2c3d.k8s.ondigitalocean.com"
+        );
+        assert!(server_url(&kubeconfig, Some("other")).is_err());
+        assert!(server_url(&kubeconfig, Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let kubeconfig = parse_kubeconfig(KUBECONFIG).unwrap();
+        assert!(diff(&kubeconfig, &kubeconfig).is_empty());
+
+        let recreated = parse_kubeconfig(
+            KUBECONFIG
+                .replace("9a1b2c3d", "4e5f6a7b")
+                .replace("- name: other\n", "- name: new\n")
+                .as_str(),
+        )
+        .unwrap();
+        let diff = diff(&kubeconfig, &recreated);
+
+        assert_eq!(
+            diff.clusters,
+            NamedEntriesDiff {
+                added: vec![],
+                removed: vec![],
+                changed: vec!["do-fra1-cluster".to_string()],
+            }
+        );
+        assert_eq!(
+            diff.contexts,
+            NamedEntriesDiff {
+                added: vec!["new".to_string()],
+                removed: vec!["other".to_string()],
+                changed: vec![],
+            }
+        );
+        assert!(diff.users.is_empty());
+    }
+
+    #[test]
+    fn test_kubeconfig_guard() {
+        let path = std::env::temp_dir().join(format!("kubeconfig-guard-{}", std::process::id()));
+        std::fs::write(&path, KUBECONFIG).unwrap();
+
+        {
+            let kubeconfig = KubeconfigGuard::new(&path);
+            assert!(kubeconfig.path().exists());
+        }
+
+        assert!(!path.exists());
+        // already removed
+        assert!(cleanup_kubeconfig(&path).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_kubeconfig_path() {
+        let home_dir = Some(PathBuf::from("/home/qovery"));
+
+        assert_eq!(
+            kubeconfig_path(None, home_dir.clone()),
+            Some(PathBuf::from("/home/qovery/.kube/config"))
+        );
+        assert_eq!(
+            kubeconfig_path(
+                Some(OsStr::new(":/tmp/first:/tmp/second")),
+                home_dir.clone()
+            ),
+            Some(PathBuf::from("/tmp/first"))
+        );
+        assert_eq!(
+            kubeconfig_path(Some(OsStr::new("")), home_dir),
+            Some(PathBuf::from("/home/qovery/.kube/config"))
+        );
+        assert_eq!(kubeconfig_path(None, None), None);
+    }
+}
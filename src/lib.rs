@@ -15,7 +15,11 @@ pub mod engine;
 pub mod error;
 pub mod fs;
 pub mod git;
+pub mod kubeconfig;
+pub mod metrics;
 pub mod models;
+pub mod object_storage;
+pub mod proxy;
 mod runtime;
 pub mod s3;
 pub mod session;
@@ -24,4 +28,3 @@ mod template;
 pub mod transaction;
 mod unit_conversion;
 mod utilities;
-mod object_storage;
@@ -0,0 +1,44 @@
+use std::sync::{Arc, RwLock};
+
+/// number of executed commands, labelled by binary
+pub const COMMAND_COUNT: &str = "command.count";
+/// number of commands that failed (non zero exit code or unable to run), labelled by binary
+pub const COMMAND_FAILURE_COUNT: &str = "command.failure.count";
+/// duration of the commands in seconds, labelled by binary
+pub const COMMAND_DURATION_SECONDS: &str = "command.duration.seconds";
+/// number of retries of an operation, labelled by operation
+pub const RETRY_COUNT: &str = "retry.count";
+/// number of DigitalOcean API calls, labelled by status
+pub const DIGITALOCEAN_API_CALL_COUNT: &str = "digitalocean.api.call.count";
+/// duration of the DigitalOcean API calls in seconds
+pub const DIGITALOCEAN_API_CALL_DURATION_SECONDS: &str = "digitalocean.api.call.duration.seconds";
+
+/// where the engine records its metrics (counters and histograms).
+/// Every method does nothing by default, so an implementation only has to handle what it cares about.
+pub trait Metrics: Send + Sync {
+    /// add `value` to the counter `name`
+    fn increment(&self, _name: &str, _value: u64, _labels: &[(&str, &str)]) {}
+    /// record `value` in the histogram `name`
+    fn observe(&self, _name: &str, _value: f64, _labels: &[(&str, &str)]) {}
+}
+
+/// default metrics recorder, it drops everything
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+static METRICS: RwLock<Option<Arc<dyn Metrics>>> = RwLock::new(None);
+
+/// set the metrics recorder used by the whole engine. `None` (the default) disables metrics.
+pub fn set_metrics(metrics: Option<Arc<dyn Metrics>>) {
+    let mut current_metrics = METRICS.write().unwrap();
+    *current_metrics = metrics;
+}
+
+/// the metrics recorder set with `set_metrics`, or a no-op one
+pub fn metrics() -> Arc<dyn Metrics> {
+    match METRICS.read().unwrap().as_ref() {
+        Some(metrics) => metrics.clone(),
+        None => Arc::new(NoopMetrics),
+    }
+}
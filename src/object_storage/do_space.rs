@@ -1,11 +1,70 @@
+use bytes::Bytes;
+use crate::cloud_provider::digitalocean::region::DoRegion;
+use crate::constants::USER_AGENT;
+use crate::crypto::file_md5;
 use crate::error::{SimpleError, SimpleErrorKind};
-use crate::s3::get_object;
+use crate::metrics::{metrics, RETRY_COUNT};
+use crate::object_storage::{FileContent, ObjectStorage};
+use futures::channel::mpsc;
+use futures::{stream, SinkExt, StreamExt, TryStreamExt};
+use reqwest::Url;
+use rusoto_core::request::BufferedHttpResponse;
+use rusoto_core::ByteStream;
 use rusoto_core::{Client, HttpClient, Region, RusotoError};
-use rusoto_credential::StaticProvider;
-use rusoto_s3::{GetObjectError, GetObjectOutput, GetObjectRequest, S3Client, S3};
+use rusoto_credential::{AwsCredentials, StaticProvider};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, DeleteObjectRequest, GetObjectError,
+    GetObjectRequest, HeadObjectError, HeadObjectRequest, ListObjectsV2Error, ListObjectsV2Request,
+    PutObjectRequest, S3Client, UploadPartRequest, S3,
+};
+use std::fs::{File, OpenOptions};
+use std::io::Error;
 use std::io::Read;
-use std::io::{Cursor, Error};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
 use tokio::runtime::{Builder, Runtime};
+
+/// read `size` bytes from the reader, or less if it ends before
+fn read_part<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>, SimpleError> {
+    let mut part = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+/// maximum expiry of a presigned URL allowed by S3
+const PRESIGNED_URL_MAX_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// size of the chunks read from the body of a streaming upload
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// size of the parts of a multipart upload, each part is held in memory while it is uploaded.
+/// S3 requires at least 5 MiB per part (except the last one) and at most 10 000 parts.
+const MULTIPART_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// number of parts of a multipart upload uploaded at the same time
+const MULTIPART_CONCURRENCY: usize = 4;
+
+/// a streaming upload bigger than this is a multipart upload. S3 can't upload more than 5 GB in a
+/// single request, and parts uploaded concurrently make large uploads faster.
+const MULTIPART_UPLOAD_THRESHOLD: u64 = 100 * 1024 * 1024;
+
+/// number of attempts of a download before giving up, each retry resumes where the previous one stopped
+const DOWNLOAD_MAX_ATTEMPTS: u64 = 5;
+
+enum DownloadFailure {
+    /// there is no point in retrying
+    Fatal(SimpleError),
+    /// the transfer was interrupted, it can be resumed
+    Interrupted(String),
+    /// the object has been replaced since the download started, it must be downloaded again from scratch
+    ObjectChanged,
+}
+
 struct Sync_do_space {
     client: S3Client,
     runtime: Runtime,
@@ -13,20 +72,18 @@ struct Sync_do_space {
 
 // implement synchronous way to download s3 objects... yeah !
 impl Sync_do_space {
-    fn new(access_key_id: &str, secret_access_key: &str, region: &str) -> Result<Self, Error> {
+    fn new(access_key_id: &str, secret_access_key: &str, region: Region) -> Result<Self, Error> {
         let credentials = StaticProvider::new(
             access_key_id.to_string(),
             secret_access_key.to_string(),
             None,
             None,
         );
-        let client = Client::new_with(credentials, HttpClient::new().unwrap());
-        let endpoint_region = Region::Custom {
-            name: region.to_string(),
-            endpoint: format!("https://{}.digitaloceanspaces.com",region),
-        };
+        let mut http_client = HttpClient::new().unwrap();
+        http_client.local_agent(USER_AGENT.to_string());
+        let client = Client::new_with(credentials, http_client);
         Ok(Sync_do_space {
-            client: S3Client::new_with_client(client, endpoint_region),
+            client: S3Client::new_with_client(client, region),
             runtime: Builder::new().basic_scheduler().enable_all().build()?,
         })
     }
@@ -42,6 +99,623 @@ impl Sync_do_space {
                     .read_to_string(&mut body);
                 Ok(body)
             }
+            // the object may not have been uploaded yet, this is worth a retry
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(e))) => Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!("object not found: {}", e)),
+            )),
+            // the bucket does not exist, this is a configuration error and retrying won't help
+            Err(RusotoError::Unknown(ref res)) if is_no_such_bucket(res) => Err(SimpleError::new(
+                SimpleErrorKind::BucketNotFound,
+                Some("bucket not found"),
+            )),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    fn put_object(&mut self, request: PutObjectRequest) -> Result<(), SimpleError> {
+        match self.runtime.block_on(self.client.put_object(request)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    /// stream the `length` bytes of the reader into the object, without holding them in memory
+    fn put_object_stream<R: Read>(
+        &mut self,
+        bucket_name: &str,
+        object_key: &str,
+        reader: &mut R,
+        length: u64,
+    ) -> Result<(), SimpleError> {
+        let (mut sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+        let request = PutObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            content_length: Some(length as i64),
+            body: Some(ByteStream::new_with_size(receiver, length as usize)),
+            ..Default::default()
+        };
+
+        let client = &self.client;
+        let (response, read_result) = self.runtime.block_on(async move {
+            let send_body = async move {
+                let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+                loop {
+                    let size = match reader.read(&mut buffer) {
+                        Ok(0) => return Ok(()),
+                        Ok(size) => size,
+                        Err(e) => {
+                            let message = e.to_string();
+                            // fail the request too, instead of sending a truncated body
+                            let _ = sender.send(Err(e)).await;
+                            return Err(message);
+                        }
+                    };
+
+                    if sender.send(Ok(Bytes::copy_from_slice(&buffer[..size]))).await.is_err() {
+                        // the request is over (failed), its error is returned
+                        return Ok(());
+                    }
+                }
+            };
+
+            futures::join!(client.put_object(request), send_body)
+        });
+
+        if let Err(e) = read_result {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!("unable to read the body of {}: {}", object_key, e)),
+            ));
+        }
+
+        match response {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    /// upload the reader into the object in parts of `MULTIPART_PART_SIZE`, its length does not
+    /// have to be known. If the upload fails, it is aborted so the uploaded parts are not left behind
+    /// (they would be billed until then).
+    fn multipart_upload<R: Read>(
+        &mut self,
+        bucket_name: &str,
+        object_key: &str,
+        reader: &mut R,
+    ) -> Result<(), SimpleError> {
+        let request = CreateMultipartUploadRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            ..Default::default()
+        };
+
+        let upload_id = match self.runtime.block_on(self.client.create_multipart_upload(request)) {
+            Ok(output) => match output.upload_id {
+                Some(upload_id) => upload_id,
+                None => {
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some("no upload id returned for the multipart upload"),
+                    ))
+                }
+            },
+            Err(e) => {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(e.to_string()),
+                ))
+            }
+        };
+
+        let result = self
+            .upload_parts(bucket_name, object_key, upload_id.as_str(), reader)
+            .and_then(|parts| {
+                let request = CompleteMultipartUploadRequest {
+                    bucket: bucket_name.to_string(),
+                    key: object_key.to_string(),
+                    upload_id: upload_id.clone(),
+                    multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                    ..Default::default()
+                };
+
+                match self.runtime.block_on(self.client.complete_multipart_upload(request)) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(e.to_string()),
+                    )),
+                }
+            });
+
+        if result.is_err() {
+            let request = AbortMultipartUploadRequest {
+                bucket: bucket_name.to_string(),
+                key: object_key.to_string(),
+                upload_id: upload_id.clone(),
+                ..Default::default()
+            };
+
+            if let Err(e) = self.runtime.block_on(self.client.abort_multipart_upload(request)) {
+                error!(
+                    "unable to abort the multipart upload {} of {}, its parts are left behind: {}",
+                    upload_id, object_key, e
+                );
+            }
+        }
+
+        result
+    }
+
+    /// upload the parts, `MULTIPART_CONCURRENCY` at a time, stopping at the first failure
+    fn upload_parts<R: Read>(
+        &mut self,
+        bucket_name: &str,
+        object_key: &str,
+        upload_id: &str,
+        reader: &mut R,
+    ) -> Result<Vec<CompletedPart>, SimpleError> {
+        let mut read_error = None;
+        let mut part_count = 0;
+        let mut last_part_read = false;
+        let parts = std::iter::from_fn(|| {
+            if last_part_read {
+                return None;
+            }
+
+            let part = match read_part(reader, MULTIPART_PART_SIZE) {
+                Ok(part) => part,
+                Err(e) => {
+                    read_error = Some(e);
+                    return None;
+                }
+            };
+
+            // an empty object still needs one (empty) part
+            if part.is_empty() && part_count > 0 {
+                return None;
+            }
+
+            part_count += 1;
+            last_part_read = part.len() < MULTIPART_PART_SIZE;
+            Some((part_count as i64, part))
+        });
+
+        let client = &self.client;
+        let uploads = stream::iter(parts)
+            .map(|(part_number, part)| {
+                let request = UploadPartRequest {
+                    bucket: bucket_name.to_string(),
+                    key: object_key.to_string(),
+                    upload_id: upload_id.to_string(),
+                    part_number,
+                    content_length: Some(part.len() as i64),
+                    body: Some(part.into()),
+                    ..Default::default()
+                };
+
+                async move {
+                    match client.upload_part(request).await {
+                        Ok(output) => Ok(CompletedPart {
+                            e_tag: output.e_tag,
+                            part_number: Some(part_number),
+                        }),
+                        Err(e) => Err(SimpleError::new(
+                            SimpleErrorKind::Other,
+                            Some(format!("unable to upload the part {}: {}", part_number, e)),
+                        )),
+                    }
+                }
+            })
+            .buffer_unordered(MULTIPART_CONCURRENCY)
+            .try_collect::<Vec<_>>();
+
+        let mut completed_parts = self.runtime.block_on(uploads)?;
+        if let Some(e) = read_error {
+            return Err(e);
+        }
+
+        // the parts must be listed in order to complete the upload
+        completed_parts.sort_by_key(|part| part.part_number);
+        Ok(completed_parts)
+    }
+
+    fn delete_object(&mut self, request: DeleteObjectRequest) -> Result<(), SimpleError> {
+        match self.runtime.block_on(self.client.delete_object(request)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    fn head_object(&mut self, request: HeadObjectRequest) -> Result<bool, SimpleError> {
+        match self.runtime.block_on(self.client.head_object(request)) {
+            Ok(_) => Ok(true),
+            // HEAD responses have no body, so a missing key is usually reported as an unknown 404
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            Err(RusotoError::Unknown(ref res)) if res.status.as_u16() == 404 => Ok(false),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    fn list_objects(&mut self, bucket_name: &str) -> Result<Vec<String>, SimpleError> {
+        let mut keys = vec![];
+        let mut continuation_token = None;
+
+        loop {
+            let request = ListObjectsV2Request {
+                bucket: bucket_name.to_string(),
+                continuation_token,
+                ..Default::default()
+            };
+
+            let output = match self.runtime.block_on(self.client.list_objects_v2(request)) {
+                Ok(output) => output,
+                Err(RusotoError::Service(ListObjectsV2Error::NoSuchBucket(e))) => {
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::BucketNotFound,
+                        Some(format!("bucket not found: {}", e)),
+                    ))
+                }
+                Err(e) => {
+                    return Err(SimpleError::new(
+                        SimpleErrorKind::Other,
+                        Some(e.to_string()),
+                    ))
+                }
+            };
+
+            keys.extend(
+                output
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key),
+            );
+
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated.unwrap_or(false) => {
+                    continuation_token = Some(token)
+                }
+                _ => return Ok(keys),
+            }
+        }
+    }
+
+    fn list_buckets(&mut self) -> Result<Vec<String>, SimpleError> {
+        match self.runtime.block_on(self.client.list_buckets()) {
+            Ok(output) => Ok(output
+                .buckets
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|bucket| bucket.name)
+                .collect()),
+            Err(e) => Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    /// stream the object into the file, resuming with a `Range` request from the bytes already written
+    /// if the transfer is interrupted. `If-Match` makes sure a resumed request still targets the same
+    /// object version (ETag), and the final file size is checked against the object size.
+    fn download_object_to_file(
+        &mut self,
+        bucket_name: &str,
+        object_key: &str,
+        file_path: &Path,
+    ) -> Result<(), SimpleError> {
+        let mut etag: Option<String> = None;
+        let mut attempt = 0;
+        File::create(file_path)?;
+
+        loop {
+            attempt += 1;
+            let written = file_path.metadata()?.len();
+
+            let request = GetObjectRequest {
+                bucket: bucket_name.to_string(),
+                key: object_key.to_string(),
+                range: if written > 0 {
+                    Some(format!("bytes={}-", written))
+                } else {
+                    None
+                },
+                if_match: if written > 0 { etag.clone() } else { None },
+                ..Default::default()
+            };
+
+            let client = &self.client;
+            let mut response_etag = None;
+            let response_etag_ref = &mut response_etag;
+            let result = self.runtime.block_on(async move {
+                let output = match client.get_object(request).await {
+                    Ok(output) => output,
+                    Err(RusotoError::Service(GetObjectError::NoSuchKey(e))) => {
+                        return Err(DownloadFailure::Fatal(SimpleError::new(
+                            SimpleErrorKind::NotFound,
+                            Some(format!("object not found: {}", e)),
+                        )))
+                    }
+                    Err(RusotoError::Unknown(ref res)) if is_no_such_bucket(res) => {
+                        return Err(DownloadFailure::Fatal(SimpleError::new(
+                            SimpleErrorKind::BucketNotFound,
+                            Some("bucket not found"),
+                        )))
+                    }
+                    // If-Match failed
+                    Err(RusotoError::Unknown(ref res)) if res.status.as_u16() == 412 => {
+                        return Err(DownloadFailure::ObjectChanged)
+                    }
+                    Err(e) => return Err(DownloadFailure::Interrupted(e.to_string())),
+                };
+                *response_etag_ref = output.e_tag.clone();
+
+                let mut file = match OpenOptions::new().append(true).open(file_path) {
+                    Ok(file) => file,
+                    Err(e) => return Err(DownloadFailure::Fatal(SimpleError::from(e))),
+                };
+
+                let mut reader = match output.body {
+                    Some(body) => body.into_async_read(),
+                    None => return Ok(written),
+                };
+
+                let mut buffer = vec![0; 64 * 1024];
+                loop {
+                    let read = match reader.read(&mut buffer).await {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) => return Err(DownloadFailure::Interrupted(e.to_string())),
+                    };
+
+                    if let Err(e) = file.write_all(&buffer[..read]) {
+                        return Err(DownloadFailure::Fatal(SimpleError::from(e)));
+                    }
+                }
+
+                let expected_size = written + output.content_length.unwrap_or(0) as u64;
+                Ok(expected_size)
+            });
+
+            let result = match (&etag, response_etag) {
+                (Some(expected), Some(received)) if *expected != received => {
+                    Err(DownloadFailure::ObjectChanged)
+                }
+                (None, Some(received)) => {
+                    etag = Some(received);
+                    result
+                }
+                _ => result,
+            };
+
+            let reason = match result {
+                Ok(expected_size) => {
+                    let size = file_path.metadata()?.len();
+                    if size == expected_size {
+                        match etag.as_deref().and_then(etag_md5) {
+                            // corrupted, downloaded again from the start
+                            Some(md5) if file_md5(file_path)? != md5 => {
+                                File::create(file_path)?;
+                                etag = None;
+                                "the downloaded content does not match its ETag md5".to_string()
+                            }
+                            _ => return Ok(()),
+                        }
+                    } else {
+                        if size > expected_size {
+                            File::create(file_path)?;
+                        }
+                        format!("downloaded {} bytes instead of {}", size, expected_size)
+                    }
+                }
+                Err(DownloadFailure::Fatal(err)) => return Err(err),
+                Err(DownloadFailure::ObjectChanged) => {
+                    File::create(file_path)?;
+                    etag = None;
+                    "the object has changed since the download started".to_string()
+                }
+                Err(DownloadFailure::Interrupted(reason)) => reason,
+            };
+
+            if attempt >= DOWNLOAD_MAX_ATTEMPTS {
+                return Err(SimpleError::new(
+                    SimpleErrorKind::Other,
+                    Some(format!(
+                        "unable to download {}/{} after {} attempts: {}",
+                        bucket_name, object_key, attempt, reason
+                    )),
+                ));
+            }
+
+            metrics().increment(RETRY_COUNT, 1, &[("operation", "spaces_download")]);
+            warn!(
+                "download of {}/{} failed ({}), resuming it (attempt {}/{})",
+                bucket_name, object_key, reason, attempt, DOWNLOAD_MAX_ATTEMPTS
+            );
+            thread::sleep(Duration::from_secs(attempt));
+        }
+    }
+}
+
+/// md5 of the object content held by its ETag. The ETag of an object uploaded in parts
+/// ("<md5 of the parts md5>-<number of parts>") is not the md5 of its content.
+fn etag_md5(etag: &str) -> Option<&str> {
+    let etag = etag.trim_matches('"');
+    match etag.len() == 32 && etag.chars().all(|x| x.is_ascii_hexdigit()) {
+        true => Some(etag),
+        false => None,
+    }
+}
+
+/// Spaces endpoint of the region, ex: https://fra1.digitaloceanspaces.com.
+/// It fails with `SimpleErrorKind::NotFound` if Spaces is not available in the region.
+pub fn spaces_endpoint(region: &str) -> Result<Url, SimpleError> {
+    let region = match region.parse::<DoRegion>() {
+        Ok(region) if region.has_spaces() => region,
+        Ok(_) => {
+            return Err(SimpleError::new(
+                SimpleErrorKind::NotFound,
+                Some(format!(
+                    "{} is not a Digital Ocean Spaces region (available regions: {})",
+                    region,
+                    DoRegion::spaces_regions()
+                        .iter()
+                        .map(|x| x.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            ))
+        }
+        Err(err) => return Err(SimpleError::new(SimpleErrorKind::NotFound, err.message)),
+    };
+
+    match Url::parse(format!("https://{}.digitaloceanspaces.com", region).as_str()) {
+        Ok(url) => Ok(url),
+        Err(e) => Err(SimpleError::new(
+            SimpleErrorKind::Other,
+            Some(format!("invalid Spaces endpoint for region {}: {}", region, e)),
+        )),
+    }
+}
+
+fn space_region(region: &str) -> Result<Region, SimpleError> {
+    let endpoint = spaces_endpoint(region)?;
+    Ok(Region::Custom {
+        name: region.to_string(),
+        endpoint: endpoint.as_str().trim_end_matches('/').to_string(),
+    })
+}
+
+fn is_no_such_bucket(response: &BufferedHttpResponse) -> bool {
+    response.status.as_u16() == 404
+        && String::from_utf8_lossy(response.body.as_ref()).contains("<Code>NoSuchBucket</Code>")
+}
+
+/// DigitalOcean Spaces object storage
+pub struct DoSpace {
+    access_key_id: String,
+    secret_access_key: String,
+    region: String,
+}
+
+impl DoSpace {
+    pub fn new(access_key_id: &str, secret_access_key: &str, region: &str) -> Self {
+        DoSpace {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            region: region.to_string(),
+        }
+    }
+
+    /// download the object into the file without holding it in memory.
+    /// An interrupted transfer is resumed from the bytes already written instead of starting over.
+    pub fn download_to_file(
+        &self,
+        bucket_name: &str,
+        object_key: &str,
+        file_path: &Path,
+    ) -> Result<(), SimpleError> {
+        self.client()?
+            .download_object_to_file(bucket_name, object_key, file_path)
+    }
+
+    /// stream the reader into the object without holding it in memory. If the `length` is unknown
+    /// or bigger than `MULTIPART_UPLOAD_THRESHOLD`, the object is uploaded in parts (multipart upload).
+    pub fn upload_from_reader<R: Read>(
+        &self,
+        bucket_name: &str,
+        object_key: &str,
+        mut reader: R,
+        length: Option<u64>,
+    ) -> Result<(), SimpleError> {
+        let mut client = self.client()?;
+        match length {
+            Some(length) if length <= MULTIPART_UPLOAD_THRESHOLD => {
+                client.put_object_stream(bucket_name, object_key, &mut reader, length)
+            }
+            _ => client.multipart_upload(bucket_name, object_key, &mut reader),
+        }
+    }
+
+    /// presigned (SigV4) URL giving temporary read access to the object without sharing the credentials.
+    /// `expiry` can't exceed 7 days, the maximum allowed by S3.
+    pub fn presign_get(
+        &self,
+        bucket_name: &str,
+        object_key: &str,
+        expiry: Duration,
+    ) -> Result<String, SimpleError> {
+        if expiry > PRESIGNED_URL_MAX_EXPIRY || expiry.as_secs() == 0 {
+            return Err(SimpleError::new(
+                SimpleErrorKind::Other,
+                Some(format!(
+                    "presigned URL expiry must be between 1s and {}s, got {}s",
+                    PRESIGNED_URL_MAX_EXPIRY.as_secs(),
+                    expiry.as_secs()
+                )),
+            ));
+        }
+
+        let request = GetObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            ..Default::default()
+        };
+
+        let credentials = AwsCredentials::new(
+            self.access_key_id.as_str(),
+            self.secret_access_key.as_str(),
+            None,
+            None,
+        );
+
+        Ok(request.get_presigned_url(
+            &space_region(self.region.as_str())?,
+            &credentials,
+            &PreSignedRequestOption { expires_in: expiry },
+        ))
+    }
+
+    /// names of the buckets of the account
+    pub fn list_buckets(&self) -> Result<Vec<String>, SimpleError> {
+        self.client()?.list_buckets()
+    }
+
+    /// fail with `SimpleErrorKind::BucketNotFound` if the bucket does not exist (ex: before downloading from it)
+    pub fn ensure_bucket_exists(&self, bucket_name: &str) -> Result<(), SimpleError> {
+        match self.list_buckets()?.iter().any(|x| x == bucket_name) {
+            true => Ok(()),
+            false => Err(SimpleError::new(
+                SimpleErrorKind::BucketNotFound,
+                Some(format!("bucket {} not found in the {} region", bucket_name, self.region)),
+            )),
+        }
+    }
+
+    fn client(&self) -> Result<Sync_do_space, SimpleError> {
+        match Sync_do_space::new(
+            self.access_key_id.as_str(),
+            self.secret_access_key.as_str(),
+            space_region(self.region.as_str())?,
+        ) {
+            Ok(client) => Ok(client),
             Err(e) => Err(SimpleError::new(
                 SimpleErrorKind::Other,
                 Some(e.to_string()),
@@ -50,6 +724,58 @@ impl Sync_do_space {
     }
 }
 
+impl ObjectStorage for DoSpace {
+    fn get(&self, bucket_name: &str, object_key: &str) -> Result<FileContent, SimpleError> {
+        let or = GetObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            ..Default::default()
+        };
+        self.client()?.get_object(or)
+    }
+
+    fn get_to_file(
+        &self,
+        bucket_name: &str,
+        object_key: &str,
+        file_path: &Path,
+    ) -> Result<(), SimpleError> {
+        self.download_to_file(bucket_name, object_key, file_path)
+    }
+
+    fn put(&self, bucket_name: &str, object_key: &str, body: &[u8]) -> Result<(), SimpleError> {
+        let pr = PutObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            body: Some(body.to_vec().into()),
+            ..Default::default()
+        };
+        self.client()?.put_object(pr)
+    }
+
+    fn delete(&self, bucket_name: &str, object_key: &str) -> Result<(), SimpleError> {
+        let dr = DeleteObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            ..Default::default()
+        };
+        self.client()?.delete_object(dr)
+    }
+
+    fn exists(&self, bucket_name: &str, object_key: &str) -> Result<bool, SimpleError> {
+        let hr = HeadObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: object_key.to_string(),
+            ..Default::default()
+        };
+        self.client()?.head_object(hr)
+    }
+
+    fn list(&self, bucket_name: &str) -> Result<Vec<String>, SimpleError> {
+        self.client()?.list_objects(bucket_name)
+    }
+}
+
 pub fn download_space_object(
     access_key_id: &str,
     secret_access_key: &str,
@@ -57,21 +783,132 @@ pub fn download_space_object(
     object_key: &str,
     region: &str
 ) -> Result<String, SimpleError> {
-    let sync_do_space = Sync_do_space::new(access_key_id, secret_access_key,region);
-    match sync_do_space {
-        Ok(mut client) => {
-            let mut or = GetObjectRequest::default();
-            or.bucket = bucket_name.to_string();
-            or.key = object_key.to_string();
-            let res_body = client.get_object(or);
-            match res_body {
-                Ok(body) => Ok(body),
-                Err(e) => Err(e),
-            }
+    DoSpace::new(access_key_id, secret_access_key, region).get(bucket_name, object_key)
+}
+
+pub fn download_space_object_to_file(
+    access_key_id: &str,
+    secret_access_key: &str,
+    bucket_name: &str,
+    object_key: &str,
+    region: &str,
+    file_path: &Path,
+) -> Result<(), SimpleError> {
+    DoSpace::new(access_key_id, secret_access_key, region).download_to_file(
+        bucket_name,
+        object_key,
+        file_path,
+    )
+}
+
+pub fn upload_space_object_stream<R: Read>(
+    access_key_id: &str,
+    secret_access_key: &str,
+    bucket_name: &str,
+    object_key: &str,
+    region: &str,
+    reader: R,
+    length: Option<u64>,
+) -> Result<(), SimpleError> {
+    DoSpace::new(access_key_id, secret_access_key, region).upload_from_reader(
+        bucket_name,
+        object_key,
+        reader,
+        length,
+    )
+}
+
+pub fn list_spaces_buckets(
+    access_key_id: &str,
+    secret_access_key: &str,
+    region: &str,
+) -> Result<Vec<String>, SimpleError> {
+    DoSpace::new(access_key_id, secret_access_key, region).list_buckets()
+}
+
+pub fn presign_space_object(
+    access_key_id: &str,
+    secret_access_key: &str,
+    bucket_name: &str,
+    object_key: &str,
+    region: &str,
+    expiry: Duration,
+) -> Result<String, SimpleError> {
+    DoSpace::new(access_key_id, secret_access_key, region).presign_get(
+        bucket_name,
+        object_key,
+        expiry,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SimpleErrorKind;
+    use crate::object_storage::do_space::{
+        etag_md5, presign_space_object, read_part, spaces_endpoint,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_presign_space_object() {
+        let url = presign_space_object(
+            "access",
+            "secret",
+            "qovery-kubeconfigs-z1234",
+            "z1234.yaml",
+            "fra1",
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+
+        assert!(url.starts_with(
+            "https://fra1.digitaloceanspaces.com/qovery-kubeconfigs-z1234/z1234.yaml?"
+        ));
+        assert!(url.contains("X-Amz-Expires=3600"));
+        assert!(url.contains("X-Amz-Signature="));
+
+        let too_long = Duration::from_secs(7 * 24 * 60 * 60 + 1);
+        assert!(
+            presign_space_object("access", "secret", "bucket", "key", "fra1", too_long).is_err()
+        );
+    }
+
+    #[test]
+    fn test_spaces_endpoint() {
+        assert_eq!(
+            spaces_endpoint("fra1").unwrap().as_str(),
+            "https://fra1.digitaloceanspaces.com/"
+        );
+
+        match spaces_endpoint("fra2") {
+            Err(err) => assert!(matches!(err.kind, SimpleErrorKind::NotFound)),
+            Ok(_) => panic!("fra2 is not a Spaces region"),
         }
-        Err(e) => Err(SimpleError::new(
-            SimpleErrorKind::Other,
-            Some(e.to_string()),
-        )),
+
+        let err = spaces_endpoint("lon1").unwrap_err();
+        assert_eq!(
+            err.message.unwrap(),
+            "lon1 is not a Digital Ocean Spaces region (available regions: nyc3, ams3, sfo2, sfo3, sgp1, fra1)"
+        );
+    }
+
+    #[test]
+    fn test_read_part() {
+        let mut reader: &[u8] = b"0123456789";
+
+        assert_eq!(read_part(&mut reader, 4).unwrap(), b"0123");
+        assert_eq!(read_part(&mut reader, 4).unwrap(), b"4567");
+        assert_eq!(read_part(&mut reader, 4).unwrap(), b"89");
+        assert!(read_part(&mut reader, 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_etag_md5() {
+        assert_eq!(
+            etag_md5("\"d41d8cd98f00b204e9800998ecf8427e\""),
+            Some("d41d8cd98f00b204e9800998ecf8427e")
+        );
+        // uploaded in parts
+        assert_eq!(etag_md5("\"d41d8cd98f00b204e9800998ecf8427e-3\""), None);
     }
 }
@@ -1 +1,30 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::SimpleError;
+
 pub mod do_space;
+
+pub type FileContent = String;
+
+/// Object storage provider (DO Spaces, AWS S3, MinIO...) used to store and retrieve
+/// files like kubeconfigs, independently of the underlying backend.
+pub trait ObjectStorage {
+    fn get(&self, bucket_name: &str, object_key: &str) -> Result<FileContent, SimpleError>;
+    /// write the object into the file, backends able to stream it should not hold it in memory
+    fn get_to_file(
+        &self,
+        bucket_name: &str,
+        object_key: &str,
+        file_path: &Path,
+    ) -> Result<(), SimpleError> {
+        let content = self.get(bucket_name, object_key)?;
+        fs::write(file_path, content)?;
+        Ok(())
+    }
+    fn put(&self, bucket_name: &str, object_key: &str, body: &[u8]) -> Result<(), SimpleError>;
+    fn delete(&self, bucket_name: &str, object_key: &str) -> Result<(), SimpleError>;
+    fn exists(&self, bucket_name: &str, object_key: &str) -> Result<bool, SimpleError>;
+    /// list the object keys contained in the bucket
+    fn list(&self, bucket_name: &str) -> Result<Vec<String>, SimpleError>;
+}
@@ -0,0 +1,181 @@
+use std::env;
+
+use reqwest::Url;
+
+/// outbound proxies, as set by `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` (or their lowercase versions)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProxyConfig {
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    /// hosts reached directly, a domain also matches its subdomains (ex: "digitalocean.com"
+    /// matches "api.digitalocean.com") and "*" matches every host
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn from_env() -> Self {
+        ProxyConfig {
+            http_proxy: env_var("HTTP_PROXY"),
+            https_proxy: env_var("HTTPS_PROXY"),
+            no_proxy: env_var("NO_PROXY")
+                .map(|no_proxy| parse_no_proxy(no_proxy.as_str()))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.http_proxy.is_none() && self.https_proxy.is_none()
+    }
+
+    /// whether `host` is excluded by `NO_PROXY`
+    pub fn is_excluded(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+
+        self.no_proxy.iter().any(|entry| {
+            let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(format!(".{}", domain).as_str())
+        })
+    }
+
+    /// proxy to use for the URL, `None` to reach it directly
+    pub fn proxy_for(&self, url: &Url) -> Option<String> {
+        if url.host_str().is_some_and(|host| self.is_excluded(host)) {
+            return None;
+        }
+
+        let proxy = match url.scheme() {
+            "https" => self.https_proxy.as_ref(),
+            "http" => self.http_proxy.as_ref(),
+            _ => None,
+        };
+
+        // "proxy:3128" is accepted by most tools, reqwest needs a scheme
+        proxy.map(|proxy| match proxy.contains("://") {
+            true => proxy.clone(),
+            false => format!("http://{}", proxy),
+        })
+    }
+
+    /// the proxy envs, upper and lowercase as tools read either, for commands run with `exec_with_envs`
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        let mut envs = vec![];
+
+        if let Some(http_proxy) = &self.http_proxy {
+            envs.push(("HTTP_PROXY", http_proxy.clone()));
+            envs.push(("http_proxy", http_proxy.clone()));
+        }
+
+        if let Some(https_proxy) = &self.https_proxy {
+            envs.push(("HTTPS_PROXY", https_proxy.clone()));
+            envs.push(("https_proxy", https_proxy.clone()));
+        }
+
+        if !self.no_proxy.is_empty() {
+            envs.push(("NO_PROXY", self.no_proxy.join(",")));
+            envs.push(("no_proxy", self.no_proxy.join(",")));
+        }
+
+        envs
+    }
+}
+
+/// add the proxy envs to the envs of a command (ex: `terraform`, `doctl`), an env already set is kept
+pub fn with_proxy_envs<'a>(
+    mut envs: Vec<(&'a str, &'a str)>,
+    proxy: &'a [(&'static str, String)],
+) -> Vec<(&'a str, &'a str)> {
+    for (key, value) in proxy {
+        if !envs.iter().any(|(k, _)| k == key) {
+            envs.push((key, value.as_str()));
+        }
+    }
+
+    envs
+}
+
+/// build the reqwest client with these proxies instead of the system ones,
+/// so `NO_PROXY` is honoured and the envs are read when the client is created
+pub fn configure_proxy(
+    builder: reqwest::blocking::ClientBuilder,
+    proxy: &ProxyConfig,
+) -> reqwest::blocking::ClientBuilder {
+    if proxy.is_empty() {
+        return builder.no_proxy();
+    }
+
+    let proxy = proxy.clone();
+    builder.no_proxy().proxy(reqwest::Proxy::custom(move |url| {
+        proxy
+            .proxy_for(url)
+            .and_then(|proxy_url| Url::parse(proxy_url.as_str()).ok())
+    }))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name)
+        .or_else(|_| env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn parse_no_proxy(no_proxy: &str) -> Vec<String> {
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy::{parse_no_proxy, with_proxy_envs, ProxyConfig};
+    use reqwest::Url;
+
+    fn proxy_config(no_proxy: &str) -> ProxyConfig {
+        ProxyConfig {
+            http_proxy: Some("proxy.internal:3128".to_string()),
+            https_proxy: Some("http://proxy.internal:3129".to_string()),
+            no_proxy: parse_no_proxy(no_proxy),
+        }
+    }
+
+    #[test]
+    fn test_proxy_for() {
+        let proxy = proxy_config("localhost, .example.com");
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert_eq!(
+            proxy.proxy_for(&url("https://api.digitalocean.com/v2/kubernetes/clusters")),
+            Some("http://proxy.internal:3129".to_string())
+        );
+        assert_eq!(
+            proxy.proxy_for(&url("http://releases.hashicorp.com")),
+            Some("http://proxy.internal:3128".to_string())
+        );
+        assert_eq!(proxy.proxy_for(&url("http://localhost:8080")), None);
+        assert_eq!(proxy.proxy_for(&url("https://www.example.com")), None);
+
+        let proxy = proxy_config("digitalocean.com");
+        assert_eq!(proxy.proxy_for(&url("https://api.digitalocean.com")), None);
+        assert!(proxy
+            .proxy_for(&url("https://notdigitalocean.com"))
+            .is_some());
+
+        let proxy = proxy_config("*");
+        assert_eq!(proxy.proxy_for(&url("https://api.digitalocean.com")), None);
+    }
+
+    #[test]
+    fn test_with_proxy_envs() {
+        let proxy = proxy_config("api.digitalocean.com").envs();
+
+        let envs = with_proxy_envs(vec![("HTTPS_PROXY", "http://other:3128")], &proxy);
+        assert!(envs.contains(&("HTTPS_PROXY", "http://other:3128")));
+        assert!(envs.contains(&("https_proxy", "http://proxy.internal:3129")));
+        assert!(envs.contains(&("HTTP_PROXY", "proxy.internal:3128")));
+        assert!(envs.contains(&("NO_PROXY", "api.digitalocean.com")));
+        assert_eq!(envs.iter().filter(|(k, _)| *k == "HTTPS_PROXY").count(), 1);
+
+        assert!(with_proxy_envs(vec![], &ProxyConfig::default().envs()).is_empty());
+    }
+}
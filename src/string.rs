@@ -9,3 +9,49 @@ pub fn cut(str: String, max_length: usize) -> String {
 pub fn terraform_list_format(tf_vec: Vec<String>) -> String {
     format!("{{{}}}", tf_vec.join(","))
 }
+
+/// replace every occurrence of the secrets (ex: API tokens) in the input by `****`,
+/// to be used on anything logged which may embed a secret (error messages, API responses...)
+pub fn redact_secrets(input: &str, secrets: &[&str]) -> String {
+    let mut secrets = secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .collect::<Vec<_>>();
+    // a secret containing another one must be replaced first, or it would be partially left
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+    secrets
+        .into_iter()
+        .fold(input.to_string(), |redacted, secret| {
+            redacted.replace(secret, "****")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::string::redact_secrets;
+
+    #[test]
+    fn test_redact_secrets() {
+        assert_eq!(
+            redact_secrets(
+                "401 Unauthorized for token dop_v1_abc, retrying with dop_v1_abc",
+                &["dop_v1_abc"]
+            ),
+            "401 Unauthorized for token ****, retrying with ****"
+        );
+        // partial occurrences are redacted too
+        assert_eq!(
+            redact_secrets(
+                "Authorization: Bearer dop_v1_abcdef",
+                &["abc", "dop_v1_abcdef"]
+            ),
+            "Authorization: Bearer ****"
+        );
+        assert_eq!(redact_secrets("key=xabcx", &["abc"]), "key=x****x");
+        assert_eq!(
+            redact_secrets("nothing to hide", &["", "abc"]),
+            "nothing to hide"
+        );
+    }
+}
@@ -2,6 +2,8 @@ use crate::error::StringError;
 use core::option::Option::{None, Some};
 use core::result::Result;
 use core::result::Result::{Err, Ok};
+use rand::Rng;
+use std::time::Duration;
 
 // unfortunately some proposed versions are not SemVer like Elasticache (6.x)
 // this is why we need ot have our own structure
@@ -39,3 +41,15 @@ pub fn get_version_number(version: &str) -> Result<VersionsNumber, StringError>
         patch,
     })
 }
+
+/// randomize an interval by +/- `jitter` (a fraction of the interval, between 0 and 1)
+/// so that concurrent pollers don't end up synchronized
+pub fn jittered_duration(interval: Duration, jitter: f64) -> Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+        return interval;
+    }
+
+    let factor = rand::thread_rng().gen_range(1.0 - jitter, 1.0 + jitter);
+    interval.mul_f64(factor)
+}
@@ -110,6 +110,7 @@ pub fn cloud_provider_digitalocean(context: &Context) -> DO {
             region: "eu-west-3".to_string(),
         },
     )
+    .expect("unable to create the Digital Ocean cloud provider")
 }
 
 pub fn get_kube_cluster_name_from_uuid(uuid: &str) -> String {